@@ -0,0 +1,328 @@
+use crate::{
+  conn::{ConnectionOptions, Keepalive},
+  dev::{DeviceMetadataOverride, GatewayId},
+  evt::{EventOptions, Hook, ReconcilePolicy, ReconnectHook},
+  BackoffConfig, ClientConnectError, EnetClient, NameRedaction,
+};
+use enet_proto::EncoderOptions;
+use std::{fmt, sync::Arc, time::Duration};
+use tokio::net::ToSocketAddrs;
+
+/// Number of values sent per `ITEM_VALUE_SET` request by default.
+pub(crate) const DEFAULT_SET_VALUES_BATCH_SIZE: usize = 50;
+
+/// Number of commands that can wait to be sent by default.
+pub(crate) const DEFAULT_COMMAND_QUEUE_CAPACITY: usize = 10;
+
+/// See [`EnetClientBuilder::metadata_provider`].
+#[derive(Clone)]
+pub(crate) struct MetadataProvider(
+  pub(crate) Arc<dyn Fn(u32) -> Option<DeviceMetadataOverride> + Send + Sync>,
+);
+
+impl fmt::Debug for MetadataProvider {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str("MetadataProvider")
+  }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct ClientConfig {
+  pub(crate) connection: ConnectionOptions,
+  pub(crate) events: EventOptions,
+  pub(crate) without_events: bool,
+  pub(crate) connect_retries: u32,
+  pub(crate) connect_backoff: BackoffConfig,
+  pub(crate) set_values_batch_size: usize,
+  pub(crate) command_queue_capacity: usize,
+  pub(crate) reconnect_jitter: Option<f64>,
+  pub(crate) connect_deadline: Option<Duration>,
+  pub(crate) coalesce_window: Option<Duration>,
+  pub(crate) gateway_id: GatewayId,
+  pub(crate) read_only: bool,
+  pub(crate) max_devices: Option<usize>,
+  pub(crate) metadata_provider: Option<MetadataProvider>,
+}
+
+impl Default for ClientConfig {
+  fn default() -> Self {
+    Self {
+      connection: ConnectionOptions::default(),
+      events: EventOptions::default(),
+      without_events: false,
+      connect_retries: 0,
+      connect_backoff: BackoffConfig::default(),
+      set_values_batch_size: DEFAULT_SET_VALUES_BATCH_SIZE,
+      command_queue_capacity: DEFAULT_COMMAND_QUEUE_CAPACITY,
+      reconnect_jitter: None,
+      connect_deadline: None,
+      coalesce_window: None,
+      gateway_id: GatewayId::default(),
+      read_only: false,
+      max_devices: None,
+      metadata_provider: None,
+    }
+  }
+}
+
+/// Builder for configuring an [`EnetClient`] before connecting.
+///
+/// Created by [`EnetClient::builder`].
+///
+/// There are no credentials to configure. The gateway protocol has no login
+/// step: a new connection starts with the version request, and the gateway
+/// accepts commands from any client that can reach it, so access has to be
+/// restricted on the network.
+pub struct EnetClientBuilder<A> {
+  addr: A,
+  config: ClientConfig,
+}
+
+impl<A> EnetClientBuilder<A>
+where
+  A: ToSocketAddrs + Clone + Send + Sync + 'static,
+{
+  pub(crate) fn new(addr: A) -> Self {
+    Self {
+      addr,
+      config: ClientConfig::default(),
+    }
+  }
+
+  /// Enable TCP keepalive on the command and event connections.
+  ///
+  /// This allows half-open connections to the gateway (for instance after a
+  /// wifi drop) to be detected by the operating system.
+  pub fn keepalive(mut self, keepalive: Keepalive) -> Self {
+    self.config.connection.keepalive = Some(keepalive);
+    self
+  }
+
+  /// Control how device names appear in emitted tracing events.
+  ///
+  /// Device names can reveal the layout of a household, so they can be
+  /// redacted or hashed before logs are shipped elsewhere. Defaults to
+  /// [`NameRedaction::Plain`].
+  pub fn redact_names(mut self, redaction: NameRedaction) -> Self {
+    self.config.events.redaction = redaction;
+    self
+  }
+
+  /// Never change the state of a device.
+  ///
+  /// Every method that sets values, including scenes and raw requests, then
+  /// fails with [`CommandError::ReadOnly`](crate::cmd::CommandError::ReadOnly)
+  /// before anything is sent. The command connection is still opened, as the
+  /// project is read over it, and device updates arrive as usual.
+  pub fn read_only(mut self) -> Self {
+    self.config.read_only = true;
+    self
+  }
+
+  /// Expose at most `count` devices of the project.
+  ///
+  /// Devices are kept by number, lowest first, so the same devices are kept
+  /// every time. The other ones are reported by [`EnetClient::warnings`].
+  /// This bounds the work done when connecting to a gateway that reports a
+  /// huge project. Unlimited by default.
+  pub fn max_devices(mut self, count: usize) -> Self {
+    self.config.max_devices = Some(count);
+    self
+  }
+
+  /// Look up metadata for every device, by number, while connecting.
+  ///
+  /// A name returned by `provider` replaces the one from the project in
+  /// [`EnetDevice::name`](crate::EnetDevice::name) and in the name lookups
+  /// of the client, while [`EnetDevice::raw_name`](crate::EnetDevice::raw_name)
+  /// keeps the project name. Tags are kept with the device as they are.
+  pub fn metadata_provider(
+    mut self,
+    provider: impl Fn(u32) -> Option<DeviceMetadataOverride> + Send + Sync + 'static,
+  ) -> Self {
+    self.config.metadata_provider = Some(MetadataProvider(Arc::new(provider)));
+    self
+  }
+
+  /// Identify the gateway in the [`DeviceId`](crate::dev::DeviceId)s of its
+  /// devices. Only needed when devices of several gateways are kept
+  /// together, for instance as keys in the same map.
+  pub fn gateway_id(mut self, id: impl Into<GatewayId>) -> Self {
+    self.config.gateway_id = id.into();
+    self
+  }
+
+  /// Change how requests are written, for gateway firmwares that expect
+  /// something other than what the gateway apps send.
+  pub fn encoder_options(mut self, options: EncoderOptions) -> Self {
+    self.config.connection.encoder = options;
+    self
+  }
+
+  /// Retry the initial connection up to `count` times before giving up.
+  ///
+  /// Each retry redoes the whole handshake (connecting and fetching the
+  /// version, channel info and project), waiting according to `backoff` in
+  /// between. When every attempt fails the error of the last one is
+  /// returned. A gateway that speaks an unsupported protocol is not retried.
+  pub fn connect_retries(mut self, count: u32, backoff: BackoffConfig) -> Self {
+    self.config.connect_retries = count;
+    self.config.connect_backoff = backoff;
+    self
+  }
+
+  /// Give up connecting once `deadline` has passed.
+  ///
+  /// The deadline covers the whole handshake: opening the connections,
+  /// fetching the version, channel info and project, signing in for events,
+  /// and any [`connect_retries`](Self::connect_retries) with their delays.
+  /// When it passes, [`connect`](Self::connect) returns
+  /// [`ClientConnectError::Timeout`].
+  pub fn connect_deadline(mut self, deadline: Duration) -> Self {
+    self.config.connect_deadline = Some(deadline);
+    self
+  }
+
+  /// Maximum number of values sent in a single request by
+  /// [`EnetClient::set_values`]. Larger batches are split into several
+  /// requests that are sent one after the other. Defaults to 50.
+  ///
+  /// A size of 0 is treated as 1.
+  pub fn set_values_batch_size(mut self, size: usize) -> Self {
+    self.config.set_values_batch_size = size.max(1);
+    self
+  }
+
+  /// Number of commands that can wait to be sent before further commands
+  /// wait for room in the queue. Defaults to 10.
+  ///
+  /// [`EnetClient::try_set_values`] fails instead of waiting when the queue
+  /// is full. A capacity of 0 is treated as 1.
+  pub fn command_queue_capacity(mut self, capacity: usize) -> Self {
+    self.config.command_queue_capacity = capacity.max(1);
+    self
+  }
+
+  /// Set the reconnect policy for the event connection.
+  ///
+  /// The same [`BackoffConfig`] can be shared between several clients, or
+  /// loaded from a config file. See [`BackoffConfig`] for how a missing
+  /// `max_elapsed_time` is treated.
+  pub fn backoff(mut self, backoff: BackoffConfig) -> Self {
+    self.config.events.backoff = backoff;
+    self
+  }
+
+  /// Coalesce [`EnetClient::set_value`] calls for the same device that are
+  /// made within `window` of each other.
+  ///
+  /// The first value starts the window, later values replace it, and only
+  /// the latest one is sent once the window is over. This keeps a slider
+  /// that is dragged quickly from flooding the gateway. Each device has its
+  /// own window, and the values of different devices are sent independently.
+  ///
+  /// `set_value` then returns once the value is queued, and failures to send
+  /// it are only logged. The last value of every device is still sent when
+  /// the client is closed or dropped.
+  pub fn coalesce_set_value(mut self, window: Duration) -> Self {
+    self.config.coalesce_window = Some(window);
+    self
+  }
+
+  /// Randomize every reconnect delay by up to `factor` in either direction.
+  ///
+  /// This overrides the `randomization_factor` of both the
+  /// [`backoff`](Self::backoff) and the [`connect_retries`](Self::connect_retries)
+  /// policies, regardless of the order they are set in. Without it, their own
+  /// factor is used, which is `0.5` by default. Use `0.0` for predictable
+  /// delays.
+  pub fn reconnect_jitter(mut self, factor: f64) -> Self {
+    self.config.reconnect_jitter = Some(factor);
+    self
+  }
+
+  /// Call `hook` every time the gateway confirms the sign-in for device
+  /// updates on a new event connection, including after a reconnect.
+  ///
+  /// The lifecycle hooks run on the task that reads device updates, so they
+  /// must return quickly and must not block. Only the event connection is
+  /// watched, as the command connection is not reopened when it fails.
+  /// See [`EnetClient::subscribe_connection_status`] for the same
+  /// transitions as an eventual.
+  pub fn on_connect(mut self, hook: impl Fn() + Send + Sync + 'static) -> Self {
+    self.config.events.hooks.on_connect = Some(Arc::new(hook) as Hook);
+    self
+  }
+
+  /// Call `hook` when a connection that was signed in for device updates is
+  /// lost or closed. Like [`on_connect`](Self::on_connect), it must not
+  /// block.
+  pub fn on_disconnect(mut self, hook: impl Fn() + Send + Sync + 'static) -> Self {
+    self.config.events.hooks.on_disconnect = Some(Arc::new(hook) as Hook);
+    self
+  }
+
+  /// Call `hook` with the attempt number (starting at 1) and the delay before
+  /// the event connection is reopened, every time a reconnect is scheduled.
+  /// Like [`on_connect`](Self::on_connect), it must not block.
+  pub fn on_reconnect_attempt(
+    mut self,
+    hook: impl Fn(u32, Duration) + Send + Sync + 'static,
+  ) -> Self {
+    self.config.events.hooks.on_reconnect_attempt = Some(Arc::new(hook) as ReconnectHook);
+    self
+  }
+
+  /// Choose how updates from the gateway are applied right after an
+  /// optimistic update, see [`ReconcilePolicy`].
+  ///
+  /// The gateway often repeats the old state of a device before it applies
+  /// a command, so with the default [`ReconcilePolicy::GatewayWins`] a
+  /// device can briefly flicker back to its old value.
+  /// [`ReconcilePolicy::HoldOptimistic`] hides this, at the cost of showing
+  /// a rejected command a little later.
+  pub fn reconcile_policy(mut self, policy: ReconcilePolicy) -> Self {
+    self.config.events.reconcile = policy;
+    self
+  }
+
+  /// How long the gateway has to confirm the sign-in for device updates when
+  /// connecting, 10 seconds by default. If it doesn't,
+  /// [`connect`](Self::connect) fails with
+  /// [`ConnectError::SignIn`](crate::ConnectError::SignIn).
+  pub fn sign_in_timeout(mut self, timeout: Duration) -> Self {
+    self.config.events.sign_in_timeout = timeout;
+    self
+  }
+
+  /// Only notify subscribers when a device value actually changes.
+  ///
+  /// The gateway repeats the values of all devices every time the event
+  /// connection signs in again, which happens every few minutes and after
+  /// every reconnect. With this on (the default), values equal to the last
+  /// known one are dropped before they reach subscribers.
+  pub fn deduplicate_updates(mut self, deduplicate: bool) -> Self {
+    self.config.events.deduplicate = deduplicate;
+    self
+  }
+
+  /// Don't open the event connection.
+  ///
+  /// The client then only sends commands and never receives push updates,
+  /// which suits one-shot tools that connect, set a value and exit. Device
+  /// subscriptions are closed without ever yielding a value.
+  pub fn without_events(mut self) -> Self {
+    self.config.without_events = true;
+    self
+  }
+
+  /// Connect to the gateway using the configured options.
+  pub async fn connect(mut self) -> Result<EnetClient, ClientConnectError> {
+    if let Some(factor) = self.config.reconnect_jitter {
+      self.config.events.backoff.randomization_factor = factor;
+      self.config.connect_backoff.randomization_factor = factor;
+    }
+
+    EnetClient::connect(self.addr, self.config).await
+  }
+}
@@ -1,36 +1,56 @@
+use crate::trace::{event, Level};
 use crate::{
-  conn::{Connection, RecvError, SendError},
+  conn::{Connection, Connector, RecvError, SendError},
+  stats::{CommandCounters, DecoderCounters},
   ConnectError,
 };
 use enet_proto::{
-  GetChannelInfoAllReq, GetChannelInfoAllRes, ItemSetValue, ItemValueRes, ItemValueSetReq,
-  ProjectListReq, ProjectListRes, RequestEnvelope, RequestType, Response, VersionReq, VersionRes,
+  DecoderStats, GetChannelInfoAllReq, GetChannelInfoAllRes, ItemSetValue, ItemValueRes,
+  ItemValueSetReq, ProjectListReq, ProjectListRes, RequestEnvelope, RequestType, Response,
+  ResponseKind, VersionReq, VersionRes,
 };
 use paste::paste;
 use std::{
+  collections::{BTreeSet, VecDeque},
   convert::{TryFrom, TryInto},
   fmt,
+  sync::Arc,
   time::Duration,
 };
 use thiserror::Error;
-use tokio::{
-  net::ToSocketAddrs,
-  sync::{mpsc, oneshot},
-};
-use tracing::{event, Level};
+use tokio::sync::{mpsc, oneshot};
 
-struct CommandActor<A>
-where
-  A: ToSocketAddrs + Clone + Send + Sync,
-{
+struct CommandActor {
   conn: Option<Connection>,
-  addr: A,
+  connector: Arc<dyn Connector>,
+  counters: Arc<DecoderCounters>,
+  commands: Arc<CommandCounters>,
   recv: mpsc::Receiver<ActorMessage>,
-  response_listener: Option<ResponseListener>,
+  /// Listeners for commands that have been sent, oldest first.
+  ///
+  /// The eNet protocol does not echo any correlation id in its responses, so
+  /// responses are matched to the oldest listener expecting that kind of
+  /// response. Raw requests only get the responses that no typed command is
+  /// waiting for, and a response that no listener expects is dropped rather
+  /// than failing an unrelated command.
+  response_listeners: VecDeque<ResponseListener>,
 }
 
 enum ActorMessage {
-  Send(RequestEnvelope, ResponseListener),
+  Send(Frame, ResponseListener),
+  /// Stop taking commands, wait up to the given time for the answers to the
+  /// ones that have been sent, then stop, and notify the sender.
+  Close(Duration, oneshot::Sender<()>),
+}
+
+/// What to send for a command.
+enum Frame {
+  Request(RequestEnvelope),
+
+  /// A request that is not one of the typed requests, see
+  /// [`CommandHandler::send_raw`].
+  #[cfg(feature = "raw")]
+  Raw(serde_json::Value),
 }
 
 macro_rules! define_response_listener {
@@ -39,9 +59,24 @@ macro_rules! define_response_listener {
       $(
         $res(oneshot::Sender<Result<$ty, CommandError>>),
       )*
+
+      /// Accepts any response.
+      #[cfg(feature = "raw")]
+      Raw(oneshot::Sender<Result<Response, CommandError>>),
     }
 
     impl ResponseListener {
+      /// The kind of response expected, or `None` if any response will do.
+      fn kind(&self) -> Option<ResponseKind> {
+        match self {
+          $(
+            Self::$res(_) => Some(ResponseKind::$res),
+          )*
+          #[cfg(feature = "raw")]
+          Self::Raw(_) => None,
+        }
+      }
+
       fn accept(self, res: Response) -> Result<(), (Option<Self>, Response)> {
         match self {
           $(
@@ -58,6 +93,8 @@ macro_rules! define_response_listener {
               }
             }
           )*
+          #[cfg(feature = "raw")]
+          Self::Raw(sender) => sender.send(Ok(res)).map_err(|res| (None, res.unwrap())),
         }
       }
 
@@ -66,6 +103,8 @@ macro_rules! define_response_listener {
           $(
             Self::$res(sender) => sender.send(Err(error)).map_err(Result::unwrap_err),
           )*
+          #[cfg(feature = "raw")]
+          Self::Raw(sender) => sender.send(Err(error)).map_err(Result::unwrap_err),
         }
       }
     }
@@ -84,20 +123,25 @@ macro_rules! define_response_listener {
 define_response_listener! {
   Version(VersionRes),
   GetChannelInfoAll(GetChannelInfoAllRes),
-  GetProject(ProjectListRes),
+  ProjectList(ProjectListRes),
   ItemValue(ItemValueRes),
 }
 
-impl<A> CommandActor<A>
-where
-  A: ToSocketAddrs + Clone + Send + Sync,
-{
-  fn new(conn: Connection, addr: A, recv: mpsc::Receiver<ActorMessage>) -> Self {
+impl CommandActor {
+  fn new(
+    conn: Connection,
+    connector: Arc<dyn Connector>,
+    counters: Arc<DecoderCounters>,
+    commands: Arc<CommandCounters>,
+    recv: mpsc::Receiver<ActorMessage>,
+  ) -> Self {
     Self {
       conn: Some(conn),
-      addr,
+      connector,
+      counters,
+      commands,
       recv,
-      response_listener: None,
+      response_listeners: VecDeque::new(),
     }
   }
 
@@ -118,7 +162,21 @@ where
 
       match result {
         Ok(()) => (),
-        Err(()) => return,
+        Err(()) => break,
+      }
+    }
+
+    // commands that are still queued would otherwise only see the actor go
+    // away, so fail them like the ones that were sent
+    self.recv.close();
+    while let Ok(msg) = self.recv.try_recv() {
+      match msg {
+        ActorMessage::Send(_, listener) => {
+          let _ = listener.error(ConnectionClosed.into());
+        }
+        ActorMessage::Close(_, done) => {
+          let _ = done.send(());
+        }
       }
     }
   }
@@ -126,31 +184,68 @@ where
   async fn handle_enet(&mut self, msg: Result<Response, RecvError>) -> Result<(), ()> {
     let msg = match msg {
       Ok(v) => v,
+      Err(e) if e.is_bad_frame() => {
+        // without a correlation id, the frame can only be charged to a
+        // command when there is a single one waiting and the frame is not one
+        // the gateway sends on its own. The connection itself is fine.
+        let unsolicited = self
+          .conn
+          .as_ref()
+          .and_then(Connection::last_error_cmd)
+          .map_or(false, |cmd| UNSOLICITED.iter().any(|k| k.as_str() == cmd));
+        if self.response_listeners.len() != 1 || unsolicited {
+          event!(target: "enet-client::cmd", Level::WARN, error = ?e, "dropping message that failed to decode");
+          return Ok(());
+        }
+
+        event!(target: "enet-client::cmd", Level::WARN, error = ?e, "received message that failed to decode");
+        if let Some(listener) = self.response_listeners.pop_front() {
+          let _ = listener.error(e.into());
+        }
+
+        return Ok(());
+      }
       Err(e) => {
         event!(target: "enet-client::cmd", Level::ERROR, error = ?e, "connection closed");
-        if let Some(listener) = self.response_listener.take() {
-          let _ = listener.error(ConnectionClosed.into());
-        }
+        self.fail_listeners();
         return Err(());
       }
     };
 
-    event!(target: "enet-client::cmd", Level::INFO, message.kind = ?msg.kind(), "received message");
-    match self.response_listener.take() {
+    let kind = msg.kind();
+    if is_unsolicited(kind) {
+      // no command is answered with these, so they can't belong to a listener
+      event!(target: "enet-client::cmd", Level::WARN, message.kind = kind.as_str(), "ignoring unsolicited message on command connection");
+      return Ok(());
+    }
+
+    event!(target: "enet-client::cmd", Level::INFO, message.kind = kind.as_str(), "received message");
+    let index = self
+      .response_listeners
+      .iter()
+      .position(|l| l.kind() == Some(kind))
+      .or_else(|| {
+        self
+          .response_listeners
+          .iter()
+          .position(|l| l.kind().is_none())
+      });
+
+    match index.and_then(|index| self.response_listeners.remove(index)) {
       None => {
-        event!(target: "enet-client::cmd", Level::WARN, message.kind = ?msg.kind(), "no listener available");
+        event!(target: "enet-client::cmd", Level::WARN, message.kind = msg.kind().as_str(), "no listener expects this response - dropping it");
         Ok(())
       }
 
       Some(listener) => match listener.accept(msg) {
         Ok(()) => Ok(()),
         Err((None, msg)) => {
-          event!(target: "enet-client::cmd", Level::INFO, message.kind = ?msg.kind(), "listener closed");
+          event!(target: "enet-client::cmd", Level::INFO, message.kind = msg.kind().as_str(), "listener closed");
           Ok(())
         }
 
         Err((Some(listener), msg)) => {
-          event!(target: "enet-client::cmd", Level::WARN, message.kind = ?msg.kind(), "wrong listener available");
+          event!(target: "enet-client::cmd", Level::WARN, message.kind = msg.kind().as_str(), "wrong listener available");
           let _ = listener.error(msg.into());
           Ok(())
         }
@@ -166,16 +261,22 @@ where
     };
 
     match msg {
+      ActorMessage::Close(timeout, done) => {
+        self.drain(timeout).await;
+        let _ = done.send(());
+        return Err(());
+      }
       ActorMessage::Send(req, res) => {
-        self.response_listener = Some(res);
+        self.response_listeners.push_back(res);
         let conn = match self.conn.as_mut() {
           Some(conn) => conn,
           None => {
             event!(target: "enet-client::cmd", Level::INFO, "Establishing new connection to eNet gateway.");
-            let conn = match Connection::new(self.addr.clone()).await {
+            let conn = match self.connector.connect(self.counters.clone()).await {
               Ok(conn) => conn,
               Err(e) => {
                 event!(target: "enet-client::cmd", Level::ERROR, "Failed to establish connection to eNet gateway: {:?}", e);
+                self.fail_listeners();
                 return Err(());
               }
             };
@@ -185,15 +286,29 @@ where
           }
         };
 
-        let kind = req.body.kind();
-        event!(target: "enet-client::cmd", Level::INFO, message.kind = ?kind, "Sending message");
-        match conn.send(&req).await {
-          Ok(()) => (),
+        let (kind, sent) = match &req {
+          Frame::Request(req) => (Some(req.body.kind()), conn.send(req).await),
+          #[cfg(feature = "raw")]
+          Frame::Raw(req) => (None, conn.send_raw(req).await),
+        };
+        let kind = kind.map_or("raw", |k| k.as_str());
+        match sent {
+          Ok(()) => {
+            event!(target: "enet-client::cmd", Level::INFO, message.kind = kind, "Sent message");
+            self.commands.record_sent();
+          }
           Err(e) => {
-            event!(target: "enet-client::cmd", Level::WARN, message.kind = ?kind, "Message failed to send");
-            if let Some(listener) = self.response_listener.take() {
+            event!(target: "enet-client::cmd", Level::WARN, message.kind = kind, transient = e.is_transient(), "Message failed to send");
+            let transient = e.is_transient();
+            if let Some(listener) = self.response_listeners.pop_back() {
               let _ = listener.error(e.into());
             }
+
+            // the connection is broken, so open a new one for the next command
+            if transient {
+              self.conn.take();
+              self.fail_listeners();
+            }
           }
         }
       }
@@ -202,47 +317,266 @@ where
     Ok(())
   }
 
+  /// Refuse further commands, and give the commands that have been sent
+  /// until `timeout` to be answered. Commands that are still unanswered then
+  /// fail with [`ClientClosing`].
+  async fn drain(&mut self, timeout: Duration) {
+    self.recv.close();
+    while let Ok(msg) = self.recv.try_recv() {
+      match msg {
+        ActorMessage::Send(_, listener) => {
+          let _ = listener.error(ClientClosing.into());
+        }
+        ActorMessage::Close(_, done) => {
+          let _ = done.send(());
+        }
+      }
+    }
+
+    let deadline = tokio::time::sleep(timeout);
+    tokio::pin!(deadline);
+    while !self.response_listeners.is_empty() {
+      let conn = match self.conn.as_mut() {
+        Some(conn) => conn,
+        None => break,
+      };
+
+      let result = tokio::select! {
+        enet = conn.recv() => self.handle_enet(enet).await,
+        _ = &mut deadline => Err(()),
+      };
+      if result.is_err() {
+        break;
+      }
+    }
+
+    for listener in self.response_listeners.drain(..) {
+      let _ = listener.error(ClientClosing.into());
+    }
+  }
+
   async fn sleep(&mut self) -> Result<(), ()> {
     event!(target: "enet-client::cmd", Level::INFO, "Closing command connection after 15 seconds of innactivity.");
     self.conn.take(); // drop connection
+    self.fail_listeners();
 
     Ok(())
   }
+
+  fn fail_listeners(&mut self) {
+    for listener in self.response_listeners.drain(..) {
+      let _ = listener.error(ConnectionClosed.into());
+    }
+  }
+}
+
+/// Frames the gateway pushes on its own. Some firmware versions also send
+/// these on the command connection.
+const UNSOLICITED: [ResponseKind; 2] = [ResponseKind::ItemUpdate, ResponseKind::ItemValueSignIn];
+
+fn is_unsolicited(kind: ResponseKind) -> bool {
+  UNSOLICITED.contains(&kind)
+}
+
+/// What to do when the command queue is full.
+#[derive(Clone, Copy)]
+enum Queue {
+  /// Wait until there is room in the queue.
+  Wait,
+
+  /// Fail with [`QueueFull`].
+  FailWhenFull,
 }
 
 trait Command: RequestType {
   type Response: TryFrom<Response>;
 }
 
+#[derive(Clone)]
 pub(crate) struct CommandHandler {
   sender: mpsc::Sender<ActorMessage>,
+  counters: Arc<DecoderCounters>,
+  commands: Arc<CommandCounters>,
 }
 
 impl CommandHandler {
+  /// `capacity` is the number of commands that can be waiting to be sent
+  /// before further commands have to wait for room in the queue.
   pub(crate) async fn new(
-    addr: impl ToSocketAddrs + Clone + Send + Sync + 'static,
+    connector: Arc<dyn Connector>,
+    capacity: usize,
   ) -> Result<Self, ConnectError> {
-    let conn = Connection::new(addr.clone()).await?;
-    let (sender, recv) = mpsc::channel(10);
-    tokio::spawn(CommandActor::new(conn, addr, recv).run());
+    let counters = Arc::<DecoderCounters>::default();
+    let conn = connector.connect(counters.clone()).await?;
+    let commands = Arc::<CommandCounters>::default();
+    let (sender, recv) = mpsc::channel(capacity.max(1));
+    tokio::spawn(
+      CommandActor::new(conn, connector, counters.clone(), commands.clone(), recv).run(),
+    );
+
+    Ok(Self {
+      sender,
+      counters,
+      commands,
+    })
+  }
 
-    Ok(Self { sender })
+  pub(crate) fn decoder_stats(&self) -> DecoderStats {
+    self.counters.snapshot()
   }
 
-  async fn send<C>(&mut self, command: C) -> Result<C::Response, CommandError>
+  pub(crate) fn decode_errors(&self) -> u64 {
+    self.counters.decode_errors()
+  }
+
+  /// The number of commands written to the connection, and how many
+  /// commands failed.
+  pub(crate) fn command_counts(&self) -> (u64, u64) {
+    (self.commands.sent(), self.commands.failed())
+  }
+
+  pub(crate) fn last_frame_size(&self, kind: ResponseKind) -> Option<usize> {
+    self.counters.last_frame_size(kind)
+  }
+
+  pub(crate) fn unsupported_kinds(&self) -> BTreeSet<(String, String)> {
+    self.counters.unsupported_kinds()
+  }
+
+  /// Stop the actor once the commands that have been sent are answered, or
+  /// once `timeout` has passed. See [`EnetClient::close`](crate::EnetClient::close).
+  pub(crate) async fn close(&self, timeout: Duration) {
+    let (sender, receiver) = oneshot::channel();
+    if self
+      .sender
+      .send(ActorMessage::Close(timeout, sender))
+      .await
+      .is_ok()
+    {
+      let _ = receiver.await;
+    }
+  }
+
+  /// Send a command and wait for its response.
+  ///
+  /// At TRACE level, each call is wrapped in a span that carries the request
+  /// as sent and the kind and latency of the response, so the two sides of a
+  /// command are logged together.
+  #[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(
+      level = "trace",
+      target = "enet-client::cmd",
+      name = "command",
+      skip_all,
+      fields(
+        request = tracing::field::Empty,
+        response.kind = tracing::field::Empty,
+        latency = tracing::field::Empty,
+      )
+    )
+  )]
+  async fn send<C>(&mut self, command: C, queue: Queue) -> Result<C::Response, CommandError>
   where
     C: Command,
     oneshot::Sender<Result<C::Response, CommandError>>: Into<ResponseListener>,
   {
     let envelope = RequestEnvelope::new(command);
     let (sender, receiver) = oneshot::channel::<Result<C::Response, CommandError>>();
-    let msg = ActorMessage::Send(envelope, sender.into());
-    self.sender.send(msg).await?;
+    let listener: ResponseListener = sender.into();
+
+    #[cfg(feature = "tracing")]
+    let (kind, started) = {
+      let span = tracing::Span::current();
+      if !span.is_disabled() {
+        if let Ok(request) = serde_json::to_string(&envelope) {
+          span.record("request", &request.as_str());
+        }
+      }
+
+      (listener.kind(), std::time::Instant::now())
+    };
+
+    let msg = ActorMessage::Send(Frame::Request(envelope), listener);
+    let sent = match queue {
+      Queue::Wait => self.sender.send(msg).await.map_err(CommandError::from),
+      Queue::FailWhenFull => self.sender.try_send(msg).map_err(CommandError::from),
+    };
+    let result = match sent {
+      Ok(()) => receiver.await.unwrap_or_else(|e| Err(e.into())),
+      Err(e) => Err(e),
+    };
+    if result.is_err() {
+      self.commands.record_failed();
+    }
+
+    #[cfg(feature = "tracing")]
+    {
+      let span = tracing::Span::current();
+      span.record("latency", &tracing::field::debug(started.elapsed()));
+      if result.is_ok() {
+        span.record("response.kind", &tracing::field::debug(kind));
+      }
+      event!(target: "enet-client::cmd", Level::TRACE, ok = result.is_ok(), "command finished");
+    }
 
-    Ok(receiver.await??)
+    result
   }
 }
 
+#[cfg(feature = "raw")]
+impl CommandHandler {
+  /// Send `request` as a frame, and return the next response that no typed
+  /// command is waiting for.
+  ///
+  /// `request` must be a JSON object with at least a `CMD` field. `PROTOCOL`
+  /// and `TIMESTAMP` are filled in like for any other request when missing.
+  pub(crate) async fn send_raw(
+    &mut self,
+    request: serde_json::Value,
+  ) -> Result<Response, CommandError> {
+    let request = raw_envelope(request)?;
+    let (sender, receiver) = oneshot::channel();
+    let msg = ActorMessage::Send(Frame::Raw(request), ResponseListener::Raw(sender));
+    let result = match self.sender.send(msg).await {
+      Ok(()) => receiver.await.unwrap_or_else(|e| Err(e.into())),
+      Err(e) => Err(e.into()),
+    };
+    if result.is_err() {
+      self.commands.record_failed();
+    }
+
+    result
+  }
+}
+
+#[cfg(feature = "raw")]
+fn raw_envelope(request: serde_json::Value) -> Result<serde_json::Value, SendError> {
+  use serde::ser::Error as _;
+  use serde_json::Value;
+
+  let mut request = match request {
+    Value::Object(request) => request,
+    _ => {
+      let error = serde_json::Error::custom("raw requests must be JSON objects");
+      return Err(SendError::Encode(error.into()));
+    }
+  };
+
+  // the same fields a `RequestEnvelope` adds
+  let template = serde_json::to_value(RequestEnvelope::new(VersionReq::new()))
+    .map_err(|e| SendError::Encode(e.into()))?;
+  if let Value::Object(template) = template {
+    for field in ["PROTOCOL", "TIMESTAMP"] {
+      if let Some(value) = template.get(field) {
+        request.entry(field).or_insert_with(|| value.clone());
+      }
+    }
+  }
+
+  Ok(Value::Object(request))
+}
+
 macro_rules! define_command {
   ($name:ident$((
     $($arg_i:ident : $arg_t:ty),*$(,)?
@@ -276,7 +610,7 @@ macro_rules! define_command {
             $($arg_i,)*
           )?);
 
-          Ok(self.send(req).await?)
+          Ok(self.send(req, Queue::Wait).await?)
         }
       }
     }
@@ -288,6 +622,18 @@ define_command!(get_channel_info => GetChannelInfoAllReq => GetChannelInfoAllRes
 define_command!(get_project => ProjectListReq => ProjectListRes);
 define_command!(set_values(values: Vec<ItemSetValue>) => ItemValueSetReq => ItemValueRes);
 
+impl CommandHandler {
+  /// Like [`set_values`](Self::set_values), but fails with
+  /// [`QueueFull`] instead of waiting when the command queue is full.
+  pub(crate) async fn try_set_values(
+    &mut self,
+    values: Vec<ItemSetValue>,
+  ) -> Result<ItemValueRes, SetValuesCommandError> {
+    let req = ItemValueSetReq::new(values);
+    Ok(self.send(req, Queue::FailWhenFull).await?)
+  }
+}
+
 #[non_exhaustive]
 #[derive(Debug, Error)]
 #[error("Failed to send command.")]
@@ -301,6 +647,39 @@ pub enum CommandError {
   NoResponse(#[from] NoResponse),
 
   WrongResponse(Response),
+
+  QueueFull(#[from] QueueFull),
+
+  ReadOnly(#[from] ReadOnly),
+
+  ClientClosing(#[from] ClientClosing),
+}
+
+impl CommandError {
+  /// Whether the command failed because of the connection, so that sending
+  /// it again over a new connection may succeed.
+  pub fn is_transient(&self) -> bool {
+    match self {
+      CommandError::SendError(e) => e.is_transient(),
+      CommandError::RecvError(e) => e.is_transient(),
+      CommandError::ConnectionClosed(_) => true,
+      CommandError::NoResponse(_)
+      | CommandError::WrongResponse(_)
+      | CommandError::QueueFull(_)
+      | CommandError::ReadOnly(_)
+      | CommandError::ClientClosing(_) => false,
+    }
+  }
+
+  /// Whether the gateway answered with something that could not be parsed,
+  /// or with a response to a different command.
+  pub fn is_bad_response(&self) -> bool {
+    match self {
+      CommandError::RecvError(e) => e.is_bad_frame(),
+      CommandError::WrongResponse(_) => true,
+      _ => false,
+    }
+  }
 }
 
 impl From<Response> for CommandError {
@@ -316,6 +695,16 @@ impl From<mpsc::error::SendError<ActorMessage>> for CommandError {
   }
 }
 
+impl From<mpsc::error::TrySendError<ActorMessage>> for CommandError {
+  #[inline]
+  fn from(e: mpsc::error::TrySendError<ActorMessage>) -> Self {
+    match e {
+      mpsc::error::TrySendError::Full(_) => CommandError::QueueFull(QueueFull),
+      mpsc::error::TrySendError::Closed(_) => CommandError::ConnectionClosed(ConnectionClosed),
+    }
+  }
+}
+
 impl From<oneshot::error::RecvError> for CommandError {
   #[inline]
   fn from(_: oneshot::error::RecvError) -> Self {
@@ -330,3 +719,299 @@ pub struct ConnectionClosed;
 #[derive(Debug, Error)]
 #[error("Actor did not respond closed.")]
 pub struct NoResponse;
+
+/// The command queue was full, so the command was not sent.
+#[derive(Debug, Error)]
+#[error("Command queue is full.")]
+pub struct QueueFull;
+
+/// The client was built with
+/// [`EnetClientBuilder::read_only`](crate::EnetClientBuilder::read_only), so
+/// the command was not sent.
+#[derive(Debug, Error)]
+#[error("Client is read-only.")]
+pub struct ReadOnly;
+
+/// The client was closed before the gateway answered the command, or before
+/// the command was sent.
+#[derive(Debug, Error)]
+#[error("Client is closing.")]
+pub struct ClientClosing;
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    builder::DEFAULT_COMMAND_QUEUE_CAPACITY,
+    conn::{ConnectionOptions, TcpConnector},
+    testing,
+  };
+
+  const ITEM_UPDATE_IND: &str = r#"{"CMD":"ITEM_UPDATE_IND","PROTOCOL":"0.03","VALUES":[{"NUMBER":"0","VALUE":"100","STATE":"ON","SETPOINT":"255"}]}"#;
+  const ITEM_VALUE_SIGN_IN_RES: &str = r#"{"CMD":"ITEM_VALUE_SIGN_IN_RES","PROTOCOL":"0.03"}"#;
+  const VERSION_RES: &str = r#"{"CMD":"VERSION_RES","PROTOCOL":"0.03","FIRMWARE":"0.91","HARDWARE":"73354100","ENET":"45068"}"#;
+
+  #[tokio::test]
+  async fn unsolicited_frames_do_not_disturb_pending_commands() {
+    let gateway = testing::dimmers(1)
+      .push_before("VERSION_REQ", ITEM_UPDATE_IND)
+      .push_before("VERSION_REQ", ITEM_VALUE_SIGN_IN_RES)
+      .start()
+      .await;
+    let connector = Arc::new(TcpConnector::new(
+      gateway.addr(),
+      ConnectionOptions::default(),
+    ));
+
+    let mut commands = CommandHandler::new(connector, DEFAULT_COMMAND_QUEUE_CAPACITY)
+      .await
+      .unwrap();
+    let version = commands.get_version().await.unwrap();
+    assert_eq!(version.firmware, "0.91");
+
+    // the connection is still in sync for the next command
+    let channels = commands.get_channel_info().await.unwrap();
+    assert_eq!(channels.devices.len(), 1);
+  }
+
+  #[tokio::test]
+  async fn responses_are_matched_by_kind_when_out_of_order() {
+    // the version is only answered after the channel info
+    let gateway = testing::dimmers(1)
+      .ignore("VERSION_REQ")
+      .push_before(
+        "GET_CHANNEL_INFO_ALL_REQ",
+        r#"{"CMD":"GET_CHANNEL_INFO_ALL_RES","PROTOCOL":"0.03","DEVICES":[1]}"#,
+      )
+      .reply_with("GET_CHANNEL_INFO_ALL_REQ", VERSION_RES)
+      .start()
+      .await;
+    let connector = Arc::new(TcpConnector::new(
+      gateway.addr(),
+      ConnectionOptions::default(),
+    ));
+
+    let commands = CommandHandler::new(connector, DEFAULT_COMMAND_QUEUE_CAPACITY)
+      .await
+      .unwrap();
+    let mut version = commands.clone();
+    let version = tokio::spawn(async move { version.get_version().await });
+    while gateway.requests("VERSION_REQ").is_empty() {
+      tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    let channels = commands.clone().get_channel_info().await.unwrap();
+    assert_eq!(channels.devices, vec![1]);
+    assert_eq!(version.await.unwrap().unwrap().firmware, "0.91");
+  }
+
+  #[tokio::test]
+  async fn unexpected_responses_do_not_fail_pending_commands() {
+    let gateway = testing::dimmers(1)
+      .push_before(
+        "VERSION_REQ",
+        r#"{"CMD":"ITEM_VALUE_RES","PROTOCOL":"0.03"}"#,
+      )
+      .start()
+      .await;
+    let connector = Arc::new(TcpConnector::new(
+      gateway.addr(),
+      ConnectionOptions::default(),
+    ));
+
+    let mut commands = CommandHandler::new(connector, DEFAULT_COMMAND_QUEUE_CAPACITY)
+      .await
+      .unwrap();
+    let version = commands.get_version().await.unwrap();
+    assert_eq!(version.firmware, "0.91");
+  }
+
+  #[tokio::test]
+  async fn bad_unsolicited_frames_do_not_fail_the_command() {
+    let gateway = testing::dimmers(1)
+      .push_before(
+        "VERSION_REQ",
+        r#"{"CMD":"ITEM_UPDATE_IND","PROTOCOL":"0.03","VALUES":[}"#,
+      )
+      .start()
+      .await;
+    let connector = Arc::new(TcpConnector::new(
+      gateway.addr(),
+      ConnectionOptions::default(),
+    ));
+
+    let mut commands = CommandHandler::new(connector, DEFAULT_COMMAND_QUEUE_CAPACITY)
+      .await
+      .unwrap();
+    let version = commands.get_version().await.unwrap();
+    assert_eq!(version.firmware, "0.91");
+  }
+
+  #[tokio::test]
+  async fn bad_frames_are_dropped_while_several_commands_wait() {
+    let gateway = testing::dimmers(1)
+      .ignore("VERSION_REQ")
+      .push_before("GET_CHANNEL_INFO_ALL_REQ", r#"{"CMD":}"#)
+      .start()
+      .await;
+    let connector = Arc::new(TcpConnector::new(
+      gateway.addr(),
+      ConnectionOptions::default(),
+    ));
+
+    let commands = CommandHandler::new(connector, DEFAULT_COMMAND_QUEUE_CAPACITY)
+      .await
+      .unwrap();
+    let mut version = commands.clone();
+    let version = tokio::spawn(async move { version.get_version().await });
+    while gateway.requests("VERSION_REQ").is_empty() {
+      tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    // either command could have been answered with the bad frame
+    let channels = commands.clone().get_channel_info().await.unwrap();
+    assert_eq!(channels.devices, vec![1]);
+
+    commands.close(Duration::from_millis(100)).await;
+    assert!(matches!(
+      version.await.unwrap(),
+      Err(GetVersionCommandError::Command(
+        CommandError::ClientClosing(_)
+      ))
+    ));
+  }
+
+  #[tokio::test]
+  async fn only_written_commands_count_as_sent() {
+    let gateway = testing::dimmers(1).start().await;
+    let connector = Arc::new(TcpConnector::new(
+      gateway.addr(),
+      ConnectionOptions::default(),
+    ));
+
+    let mut commands = CommandHandler::new(connector, DEFAULT_COMMAND_QUEUE_CAPACITY)
+      .await
+      .unwrap();
+    commands.get_version().await.unwrap();
+    commands.close(Duration::from_millis(100)).await;
+
+    assert!(commands.get_version().await.is_err());
+    assert_eq!(commands.command_counts(), (1, 1));
+  }
+
+  #[tokio::test]
+  async fn bad_frames_fail_the_command_but_keep_the_connection() {
+    let gateway = testing::dimmers(1)
+      .reply_with("VERSION_REQ", r#"{"CMD":}"#)
+      .start()
+      .await;
+    let connector = Arc::new(TcpConnector::new(
+      gateway.addr(),
+      ConnectionOptions::default(),
+    ));
+
+    let mut commands = CommandHandler::new(connector, DEFAULT_COMMAND_QUEUE_CAPACITY)
+      .await
+      .unwrap();
+    match commands.get_version().await {
+      Err(GetVersionCommandError::Command(e)) => {
+        assert!(e.is_bad_response());
+        assert!(!e.is_transient());
+        assert!(matches!(e, CommandError::RecvError(ref e) if e.is_bad_frame()));
+      }
+      res => panic!("unexpected result: {:?}", res.map(|v| v.firmware)),
+    }
+
+    let channels = commands.get_channel_info().await.unwrap();
+    assert_eq!(channels.devices.len(), 1);
+    assert_eq!(gateway.requests("VERSION_REQ").len(), 1);
+  }
+
+  #[tokio::test]
+  async fn close_fails_commands_that_are_not_answered_in_time() {
+    // the gateway never answers the set, as the update is ignored
+    let gateway = testing::dimmers(1)
+      .reply_with("ITEM_VALUE_SET", ITEM_UPDATE_IND)
+      .start()
+      .await;
+    let connector = Arc::new(TcpConnector::new(
+      gateway.addr(),
+      ConnectionOptions::default(),
+    ));
+
+    let commands = CommandHandler::new(connector, DEFAULT_COMMAND_QUEUE_CAPACITY)
+      .await
+      .unwrap();
+    let mut pending = commands.clone();
+    let pending = tokio::spawn(async move { pending.set_values(Vec::new()).await });
+    while gateway.requests("ITEM_VALUE_SET").is_empty() {
+      tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    commands.close(Duration::from_millis(100)).await;
+    assert!(matches!(
+      pending.await.unwrap(),
+      Err(SetValuesCommandError::Command(CommandError::ClientClosing(
+        _
+      )))
+    ));
+
+    let mut closed = commands.clone();
+    assert!(closed.get_version().await.is_err());
+  }
+
+  #[cfg(feature = "raw")]
+  #[tokio::test]
+  async fn raw_requests_are_wrapped_and_answered() {
+    let gateway = testing::dimmers(1).start().await;
+    let connector = Arc::new(TcpConnector::new(
+      gateway.addr(),
+      ConnectionOptions::default(),
+    ));
+
+    let mut commands = CommandHandler::new(connector, DEFAULT_COMMAND_QUEUE_CAPACITY)
+      .await
+      .unwrap();
+    let request = serde_json::json!({ "CMD": "VERSION_REQ" });
+    let res = commands.send_raw(request).await.unwrap();
+    assert!(res.is_version());
+
+    let frames = gateway.requests("VERSION_REQ");
+    assert!(frames[0].contains(r#""PROTOCOL":"0.03""#));
+    assert!(frames[0].contains(r#""TIMESTAMP":"#));
+
+    let not_an_object = commands.send_raw(serde_json::json!([1, 2])).await;
+    assert!(matches!(not_an_object, Err(CommandError::SendError(_))));
+  }
+
+  #[cfg(feature = "raw")]
+  #[tokio::test]
+  async fn typed_commands_are_answered_before_raw_requests() {
+    let gateway = testing::dimmers(1).start().await;
+    let connector = Arc::new(TcpConnector::new(
+      gateway.addr(),
+      ConnectionOptions::default(),
+    ));
+
+    let commands = CommandHandler::new(connector, DEFAULT_COMMAND_QUEUE_CAPACITY)
+      .await
+      .unwrap();
+    // the gateway never answers this, so it is still waiting when the
+    // version arrives
+    let mut raw = commands.clone();
+    let raw =
+      tokio::spawn(async move { raw.send_raw(serde_json::json!({ "CMD": "FOO_REQ" })).await });
+    while gateway.requests("FOO_REQ").is_empty() {
+      tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    let version = tokio::time::timeout(Duration::from_secs(1), commands.clone().get_version())
+      .await
+      .expect("the version went to the raw request")
+      .unwrap();
+    assert_eq!(version.firmware, "0.91");
+
+    commands.close(Duration::from_millis(100)).await;
+    assert!(raw.await.unwrap().is_err());
+  }
+}
@@ -0,0 +1,193 @@
+use crate::{
+  cmd::CommandHandler,
+  trace::{event, Level},
+};
+use enet_proto::{ItemSetValue, SetValue};
+use futures::{
+  future::BoxFuture,
+  stream::{FuturesUnordered, StreamExt},
+  FutureExt,
+};
+use std::{
+  collections::{BTreeMap, BTreeSet},
+  time::Duration,
+};
+use tokio::{
+  sync::{mpsc, oneshot},
+  time::Instant,
+};
+
+enum ActorMessage {
+  Set(ItemSetValue),
+  /// Drop the pending values of these devices, as newer values for them are
+  /// sent directly.
+  Cancel(Vec<u32>),
+  /// Use a new command connection for the values sent from now on.
+  Commands(CommandHandler),
+  /// Send every pending value now, and notify the sender once all of them
+  /// have been answered.
+  Flush(oneshot::Sender<()>),
+}
+
+/// The latest value for a device, and when it is due to be sent.
+struct Pending {
+  value: SetValue,
+  due: Instant,
+}
+
+struct CoalesceActor {
+  commands: CommandHandler,
+  window: Duration,
+  recv: mpsc::UnboundedReceiver<ActorMessage>,
+  pending: BTreeMap<u32, Pending>,
+  /// Devices with a value that has been sent but not answered yet. The next
+  /// value for one of them waits for the answer, so the gateway gets the
+  /// values of a device in order.
+  in_flight: BTreeSet<u32>,
+  sends: FuturesUnordered<BoxFuture<'static, u32>>,
+}
+
+impl CoalesceActor {
+  async fn run(mut self) {
+    loop {
+      let next_due = self
+        .pending
+        .iter()
+        .filter(|(number, _)| !self.in_flight.contains(number))
+        .map(|(_, pending)| pending.due)
+        .min();
+      let sleep = async {
+        match next_due {
+          Some(due) => tokio::time::sleep_until(due).await,
+          None => futures::future::pending().await,
+        }
+      };
+
+      tokio::select! {
+        msg = self.recv.recv() => match msg {
+          Some(ActorMessage::Set(value)) => self.set(value),
+          Some(ActorMessage::Cancel(numbers)) => {
+            for number in numbers {
+              self.pending.remove(&number);
+            }
+          }
+          Some(ActorMessage::Commands(commands)) => self.commands = commands,
+          Some(ActorMessage::Flush(done)) => {
+            self.flush().await;
+            let _ = done.send(());
+          }
+          None => {
+            // the client is gone, but the last values must still be applied
+            self.flush().await;
+            return;
+          }
+        },
+        Some(number) = self.sends.next() => {
+          self.in_flight.remove(&number);
+        }
+        _ = sleep => self.send_due(Instant::now()),
+      }
+    }
+  }
+
+  fn set(&mut self, item: ItemSetValue) {
+    let due = Instant::now() + self.window;
+    self
+      .pending
+      .entry(item.number)
+      .and_modify(|pending| pending.value = item.value)
+      .or_insert(Pending {
+        value: item.value,
+        due,
+      });
+  }
+
+  /// Send the pending values that are due at `now`, one request per device
+  /// so a slow answer for one device doesn't hold up the others.
+  fn send_due(&mut self, now: Instant) {
+    let due = self
+      .pending
+      .iter()
+      .filter(|(number, pending)| pending.due <= now && !self.in_flight.contains(number))
+      .map(|(number, _)| *number)
+      .collect::<Vec<_>>();
+
+    for number in due {
+      let value = self.pending.remove(&number).unwrap().value;
+      self.in_flight.insert(number);
+
+      let mut commands = self.commands.clone();
+      self.sends.push(
+        async move {
+          let item = ItemSetValue { number, value };
+          if let Err(error) = commands.set_values(vec![item]).await {
+            event!(target: "enet-client::cmd", Level::WARN, item.number = number, ?error, "failed to send coalesced value");
+          }
+
+          number
+        }
+        .boxed(),
+      );
+    }
+  }
+
+  async fn flush(&mut self) {
+    loop {
+      // values queued behind an unanswered one go out once it is answered
+      self.send_due(Instant::now() + self.window);
+      match self.sends.next().await {
+        Some(number) => {
+          self.in_flight.remove(&number);
+        }
+        None => return,
+      }
+    }
+  }
+}
+
+/// Coalesces values set for the same device within a short window, see
+/// [`EnetClientBuilder::coalesce_set_value`](crate::EnetClientBuilder::coalesce_set_value).
+pub(crate) struct Coalescer {
+  sender: mpsc::UnboundedSender<ActorMessage>,
+}
+
+impl Coalescer {
+  pub(crate) fn new(commands: CommandHandler, window: Duration) -> Self {
+    let (sender, recv) = mpsc::unbounded_channel();
+    let actor = CoalesceActor {
+      commands,
+      window,
+      recv,
+      pending: BTreeMap::new(),
+      in_flight: BTreeSet::new(),
+      sends: FuturesUnordered::new(),
+    };
+    tokio::spawn(actor.run());
+
+    Self { sender }
+  }
+
+  /// Queue a value. It replaces any value for the same device that has not
+  /// been sent yet.
+  pub(crate) fn set(&self, value: ItemSetValue) {
+    let _ = self.sender.send(ActorMessage::Set(value));
+  }
+
+  /// Drop the values that have not been sent yet for these devices. Values
+  /// that are already sent are answered before any command sent after this.
+  pub(crate) fn cancel(&self, numbers: Vec<u32>) {
+    let _ = self.sender.send(ActorMessage::Cancel(numbers));
+  }
+
+  pub(crate) fn set_commands(&self, commands: CommandHandler) {
+    let _ = self.sender.send(ActorMessage::Commands(commands));
+  }
+
+  /// Send every pending value and wait until the gateway has answered.
+  pub(crate) async fn flush(&self) {
+    let (sender, receiver) = oneshot::channel();
+    if self.sender.send(ActorMessage::Flush(sender)).is_ok() {
+      let _ = receiver.await;
+    }
+  }
+}
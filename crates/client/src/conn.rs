@@ -1,42 +1,353 @@
-use crate::enc::{EnetDecoder, EnetDecoderError, EnetEncoder, EnetEncoderError};
-use enet_proto::{RequestEnvelope, Response};
-use futures::{SinkExt, StreamExt};
+use crate::{
+  enc::{EnetDecoder, EnetDecoderError, EnetEncoder, EnetEncoderError},
+  stats::DecoderCounters,
+};
+use bytes::Bytes;
+use enet_proto::{EncoderOptions, RequestEnvelope, Response};
+use futures::{future::BoxFuture, stream::FuturesUnordered, SinkExt, Stream, StreamExt};
+use socket2::{SockRef, TcpKeepalive};
+use std::{
+  net::SocketAddr,
+  pin::Pin,
+  sync::{Arc, Mutex},
+  time::Duration,
+};
 use thiserror::Error;
 use tokio::{
-  io,
-  net::{
-    tcp::{OwnedReadHalf, OwnedWriteHalf},
-    TcpStream, ToSocketAddrs,
-  },
+  io::{self, AsyncRead, AsyncWrite},
+  net::{TcpStream, ToSocketAddrs},
+};
+use tokio_util::{
+  codec::{FramedRead, FramedWrite},
+  io::StreamReader,
 };
-use tokio_util::codec::{FramedRead, FramedWrite};
-use tracing::instrument;
+
+/// How long a connection attempt gets before the next address is tried
+/// alongside it.
+const CONNECT_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+type Reader = Pin<Box<dyn AsyncRead + Send>>;
+type Writer = Pin<Box<dyn AsyncWrite + Send>>;
 
 pub(crate) struct Connection {
-  reader: FramedRead<OwnedReadHalf, EnetDecoder>,
-  writer: FramedWrite<OwnedWriteHalf, EnetEncoder>,
+  reader: FramedRead<Reader, EnetDecoder>,
+  writer: FramedWrite<Writer, EnetEncoder>,
+}
+
+/// Source of connections to the gateway for the command and event actors.
+pub(crate) trait Connector: Send + Sync + 'static {
+  fn connect(
+    &self,
+    counters: Arc<DecoderCounters>,
+  ) -> BoxFuture<'_, Result<Connection, ConnectError>>;
+
+  /// Whether a new connection can be opened after the previous one was closed.
+  fn can_reconnect(&self) -> bool;
+}
+
+/// Connects to a gateway over TCP.
+pub(crate) struct TcpConnector<A> {
+  addr: A,
+  options: ConnectionOptions,
+}
+
+impl<A> TcpConnector<A> {
+  pub(crate) fn new(addr: A, options: ConnectionOptions) -> Self {
+    Self { addr, options }
+  }
+}
+
+impl<A> Connector for TcpConnector<A>
+where
+  A: ToSocketAddrs + Clone + Send + Sync + 'static,
+{
+  fn connect(
+    &self,
+    counters: Arc<DecoderCounters>,
+  ) -> BoxFuture<'_, Result<Connection, ConnectError>> {
+    Box::pin(Connection::new(self.addr.clone(), &self.options, counters))
+  }
+
+  #[inline]
+  fn can_reconnect(&self) -> bool {
+    true
+  }
+}
+
+/// Replays a captured session. Produces a single connection that reads the
+/// captured bytes and discards everything that is written to it.
+pub(crate) struct ReplayConnector {
+  io: Mutex<Option<(Reader, Writer)>>,
+}
+
+impl ReplayConnector {
+  pub(crate) fn new<S>(frames: S) -> Self
+  where
+    S: Stream<Item = Bytes> + Send + 'static,
+  {
+    let reader: Reader = Box::pin(StreamReader::new(frames.map(Ok::<_, io::Error>)));
+    let writer: Writer = Box::pin(io::sink());
+
+    Self {
+      io: Mutex::new(Some((reader, writer))),
+    }
+  }
+}
+
+impl Connector for ReplayConnector {
+  fn connect(
+    &self,
+    counters: Arc<DecoderCounters>,
+  ) -> BoxFuture<'_, Result<Connection, ConnectError>> {
+    let io = self.io.lock().unwrap().take();
+    Box::pin(async move {
+      match io {
+        Some((reader, writer)) => Ok(Connection::from_io(
+          reader,
+          writer,
+          counters,
+          EncoderOptions::default(),
+        )),
+        None => Err(io::Error::new(io::ErrorKind::NotConnected, "replay has ended").into()),
+      }
+    })
+  }
+
+  #[inline]
+  fn can_reconnect(&self) -> bool {
+    false
+  }
+}
+
+/// TCP keepalive settings for the gateway connections.
+///
+/// Settings that are left unset use the operating system defaults. Not all
+/// platforms support configuring the probe interval or the retry count, in
+/// which case those settings are ignored.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Keepalive {
+  idle: Option<Duration>,
+  interval: Option<Duration>,
+  retries: Option<u32>,
+}
+
+impl Keepalive {
+  #[inline]
+  pub const fn new() -> Self {
+    Self {
+      idle: None,
+      interval: None,
+      retries: None,
+    }
+  }
+
+  /// Time the connection must be idle before the first keepalive probe is sent.
+  #[inline]
+  pub const fn idle(mut self, idle: Duration) -> Self {
+    self.idle = Some(idle);
+    self
+  }
+
+  /// Time between keepalive probes.
+  #[inline]
+  pub const fn interval(mut self, interval: Duration) -> Self {
+    self.interval = Some(interval);
+    self
+  }
+
+  /// Number of unanswered probes before the connection is considered dead.
+  #[inline]
+  pub const fn retries(mut self, retries: u32) -> Self {
+    self.retries = Some(retries);
+    self
+  }
+
+  pub(crate) fn apply(&self, stream: &TcpStream) -> io::Result<()> {
+    let mut params = TcpKeepalive::new();
+    if let Some(idle) = self.idle {
+      params = params.with_time(idle);
+    }
+
+    #[cfg(any(
+      target_os = "android",
+      target_os = "freebsd",
+      target_os = "ios",
+      target_os = "linux",
+      target_os = "macos",
+      target_os = "windows",
+    ))]
+    {
+      if let Some(interval) = self.interval {
+        params = params.with_interval(interval);
+      }
+    }
+
+    #[cfg(any(
+      target_os = "android",
+      target_os = "freebsd",
+      target_os = "ios",
+      target_os = "linux",
+      target_os = "macos",
+    ))]
+    {
+      if let Some(retries) = self.retries {
+        params = params.with_retries(retries);
+      }
+    }
+
+    SockRef::from(stream).set_tcp_keepalive(&params)
+  }
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ConnectionOptions {
+  pub(crate) keepalive: Option<Keepalive>,
+  pub(crate) encoder: EncoderOptions,
+}
+
+impl ConnectionOptions {
+  fn apply(&self, stream: &TcpStream) -> io::Result<()> {
+    // requests and responses are small JSON frames, so don't wait to coalesce them
+    stream.set_nodelay(true)?;
+    if let Some(keepalive) = &self.keepalive {
+      keepalive.apply(stream)?;
+    }
+
+    Ok(())
+  }
+}
+
+/// Connect to the first of the addresses `addr` resolves to that answers.
+///
+/// Like "happy eyeballs" (RFC 8305), the addresses are tried alternating
+/// between IPv6 and IPv4, and each attempt gets a short head start before
+/// the next one is started alongside it, so an unreachable address doesn't
+/// hold up the connection for the whole OS timeout.
+async fn connect_any(addr: impl ToSocketAddrs) -> io::Result<TcpStream> {
+  let mut addrs = interleave_families(tokio::net::lookup_host(addr).await?.collect()).into_iter();
+  let mut attempts = FuturesUnordered::new();
+  let mut last_error = None;
+
+  loop {
+    let next = addrs.next();
+    let started = next.is_some();
+    if let Some(addr) = next {
+      attempts.push(TcpStream::connect(addr));
+    }
+
+    if attempts.is_empty() {
+      return Err(last_error.unwrap_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "no addresses to connect to")
+      }));
+    }
+
+    // wait for the head start to pass, or for an attempt to finish
+    let delay = tokio::time::sleep(CONNECT_ATTEMPT_DELAY);
+    tokio::pin!(delay);
+    loop {
+      tokio::select! {
+        Some(result) = attempts.next() => match result {
+          Ok(stream) => return Ok(stream),
+          Err(e) => {
+            last_error = Some(e);
+            // start the next address right away
+            break;
+          }
+        },
+        _ = &mut delay, if started => break,
+        else => break,
+      }
+    }
+  }
+}
+
+/// Order addresses alternating between the address families, starting with
+/// the family of the first one.
+fn interleave_families(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+  let first_is_v6 = addrs.first().map_or(false, |a| a.is_ipv6());
+  let (first, second): (Vec<_>, Vec<_>) =
+    addrs.into_iter().partition(|a| a.is_ipv6() == first_is_v6);
+  let mut first = first.into_iter();
+  let mut second = second.into_iter();
+
+  let mut result = Vec::new();
+  loop {
+    match (first.next(), second.next()) {
+      (None, None) => return result,
+      (a, b) => result.extend(a.into_iter().chain(b)),
+    }
+  }
 }
 
 impl Connection {
-  pub(crate) async fn new(addr: impl ToSocketAddrs) -> Result<Self, ConnectError> {
-    let stream = TcpStream::connect(addr).await?;
+  pub(crate) async fn new(
+    addr: impl ToSocketAddrs,
+    options: &ConnectionOptions,
+    counters: Arc<DecoderCounters>,
+  ) -> Result<Self, ConnectError> {
+    let stream = connect_any(addr).await?;
+    options.apply(&stream)?;
+
     let (reader, writer) = stream.into_split();
+    Ok(Self::from_io(
+      Box::pin(reader),
+      Box::pin(writer),
+      counters,
+      options.encoder,
+    ))
+  }
 
-    Ok(Self {
-      reader: FramedRead::new(reader, EnetDecoder::new()),
-      writer: FramedWrite::new(writer, EnetEncoder::new()),
-    })
+  fn from_io(
+    reader: Reader,
+    writer: Writer,
+    counters: Arc<DecoderCounters>,
+    encoder: EncoderOptions,
+  ) -> Self {
+    Self {
+      reader: FramedRead::new(reader, EnetDecoder::new(counters)),
+      writer: FramedWrite::new(writer, EnetEncoder::with_options(encoder)),
+    }
   }
 
-  #[instrument(level = "debug", target = "enet-client::con", skip(self, message), err)]
+  #[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(level = "debug", target = "enet-client::con", skip(self, message), err)
+  )]
   pub(crate) async fn send(&mut self, message: &RequestEnvelope) -> Result<(), SendError> {
+    self.feed(message).await?;
+    self.flush().await
+  }
+
+  /// Encode a message into the write buffer without flushing it, unless the
+  /// buffer is full.
+  pub(crate) async fn feed(&mut self, message: &RequestEnvelope) -> Result<(), SendError> {
+    Ok(self.writer.feed(message).await?)
+  }
+
+  /// Send a frame that is not a typed request.
+  #[cfg(feature = "raw")]
+  pub(crate) async fn send_raw(&mut self, message: &serde_json::Value) -> Result<(), SendError> {
     Ok(self.writer.send(message).await?)
   }
 
-  #[instrument(level = "debug", target = "enet-client::con", skip(self), err)]
+  /// Write all buffered messages to the connection.
+  pub(crate) async fn flush(&mut self) -> Result<(), SendError> {
+    Ok(SinkExt::<&RequestEnvelope>::flush(&mut self.writer).await?)
+  }
+
+  /// The `CMD` of the last frame that failed to parse, if it names one.
+  pub(crate) fn last_error_cmd(&self) -> Option<&str> {
+    self.reader.decoder().last_error_cmd()
+  }
+
+  #[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(level = "debug", target = "enet-client::con", skip(self), err)
+  )]
   pub(crate) async fn recv(&mut self) -> Result<Response, RecvError> {
     match self.reader.next().await {
-      Some(result) => Ok(result?),
+      Some(Ok(frame)) => Ok(frame.map_err(EnetDecoderError::from)?),
+      Some(Err(e)) => bail!(e),
       None => bail!(ConnectionClosed),
     }
   }
@@ -44,16 +355,47 @@ impl Connection {
 
 #[non_exhaustive]
 #[derive(Debug, Error)]
-#[error("Failed to connect to gateway.")]
 pub enum ConnectError {
+  #[error("Failed to connect to gateway.")]
   FailedToConnect(#[from] io::Error),
+
+  /// The event connection was opened, but the gateway did not confirm the
+  /// sign-in for device updates before the connection failed or the
+  /// sign-in timeout (see
+  /// [`EnetClientBuilder::sign_in_timeout`](crate::EnetClientBuilder::sign_in_timeout))
+  /// passed.
+  #[error("Failed to connect to gateway. It did not confirm the sign-in for device updates.")]
+  SignIn,
 }
 
 #[non_exhaustive]
 #[derive(Debug, Error)]
-#[error("Failed to send message.")]
 pub enum SendError {
-  FailedToSend(#[from] EnetEncoderError),
+  /// The message could not be serialized. Sending it again will fail the
+  /// same way.
+  #[error("Failed to send message. The message could not be encoded.")]
+  Encode(#[source] enet_proto::EnetEncoderError),
+
+  /// Writing to the connection failed. A new connection may succeed.
+  #[error("Failed to send message. Could not write to the connection.")]
+  Io(#[source] io::Error),
+}
+
+impl SendError {
+  /// Whether the error was caused by the connection rather than the message,
+  /// so that sending the message over a new connection may succeed.
+  pub fn is_transient(&self) -> bool {
+    matches!(self, SendError::Io(_))
+  }
+}
+
+impl From<EnetEncoderError> for SendError {
+  fn from(e: EnetEncoderError) -> Self {
+    match e {
+      EnetEncoderError::Wrapped(e) => SendError::Encode(e),
+      EnetEncoderError::Io(e) => SendError::Io(e),
+    }
+  }
 }
 
 #[non_exhaustive]
@@ -65,6 +407,157 @@ pub enum RecvError {
   Closed(#[from] ConnectionClosed),
 }
 
+impl RecvError {
+  /// Whether a single frame failed to parse. The connection is still usable
+  /// and the next frame can be received.
+  pub fn is_bad_frame(&self) -> bool {
+    matches!(self, RecvError::DecoderError(EnetDecoderError::Wrapped(_)))
+  }
+
+  /// Whether the error was caused by the connection rather than by what the
+  /// gateway sent, so that a new connection may succeed.
+  pub fn is_transient(&self) -> bool {
+    !self.is_bad_frame()
+  }
+}
+
 #[derive(Debug, Error)]
 #[error("Connection closed.")]
 pub struct ConnectionClosed;
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use tokio::{io::AsyncWriteExt, net::TcpListener};
+
+  const ITEM_UPDATE_IND: &str = r#"{"CMD":"ITEM_UPDATE_IND","PROTOCOL":"0.03","VALUES":[{"NUMBER":"16","VALUE":"100","STATE":"ON","SETPOINT":"255"}]}"#;
+
+  async fn connected() -> (Connection, TcpStream) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (conn, server) = tokio::join!(
+      Connection::new(addr, &ConnectionOptions::default(), Arc::default()),
+      listener.accept(),
+    );
+
+    (conn.unwrap(), server.unwrap().0)
+  }
+
+  #[test]
+  fn addresses_alternate_between_families() {
+    let addrs: Vec<SocketAddr> = [
+      "[::1]:1",
+      "[::1]:2",
+      "[::1]:3",
+      "127.0.0.1:4",
+      "127.0.0.1:5",
+    ]
+    .iter()
+    .map(|a| a.parse().unwrap())
+    .collect();
+    let ports: Vec<_> = interleave_families(addrs)
+      .into_iter()
+      .map(|a| a.port())
+      .collect();
+
+    assert_eq!(ports, vec![1, 4, 2, 5, 3]);
+  }
+
+  #[tokio::test]
+  async fn connect_falls_over_to_the_next_address() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let live = listener.local_addr().unwrap();
+    // nothing listens on the first address once the listener is dropped
+    let dead = TcpListener::bind("127.0.0.1:0")
+      .await
+      .unwrap()
+      .local_addr()
+      .unwrap();
+
+    let addrs = [dead, live];
+    let (stream, server) = tokio::join!(connect_any(&addrs[..]), listener.accept());
+    assert_eq!(stream.unwrap().peer_addr().unwrap(), live);
+    server.unwrap();
+  }
+
+  #[tokio::test]
+  async fn connection_sets_socket_options() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let options = ConnectionOptions {
+      keepalive: Some(Keepalive::new().idle(Duration::from_secs(30))),
+      ..ConnectionOptions::default()
+    };
+
+    let stream = TcpStream::connect(listener.local_addr().unwrap())
+      .await
+      .unwrap();
+    options.apply(&stream).unwrap();
+
+    assert!(stream.nodelay().unwrap());
+    assert!(SockRef::from(&stream).keepalive().unwrap());
+  }
+
+  #[tokio::test]
+  async fn replay_decodes_frames_split_across_chunks() {
+    let frame = format!("{}\r\n\r\n", ITEM_UPDATE_IND);
+    let (first, second) = frame.as_bytes().split_at(10);
+    let chunks = vec![
+      Bytes::copy_from_slice(first),
+      Bytes::copy_from_slice(second),
+    ];
+    let connector = ReplayConnector::new(futures::stream::iter(chunks));
+
+    let mut conn = connector.connect(Arc::default()).await.unwrap();
+    assert!(conn.recv().await.unwrap().is_item_update());
+    assert!(matches!(conn.recv().await, Err(RecvError::Closed(_))));
+    assert!(connector.connect(Arc::default()).await.is_err());
+  }
+
+  #[tokio::test]
+  async fn recv_resumes_after_a_bad_frame() {
+    let (mut conn, mut server) = connected().await;
+    let frames = format!("{{\"CMD\":}}\r\n\r\n{}\r\n\r\n", ITEM_UPDATE_IND);
+    server.write_all(frames.as_bytes()).await.unwrap();
+
+    let error = conn.recv().await.unwrap_err();
+    assert!(error.is_bad_frame());
+    assert!(!error.is_transient());
+    assert!(conn.recv().await.unwrap().is_item_update());
+  }
+
+  #[tokio::test]
+  async fn recv_reports_a_closed_connection_as_transient() {
+    let (mut conn, server) = connected().await;
+    drop(server);
+
+    let error = conn.recv().await.unwrap_err();
+    assert!(!error.is_bad_frame());
+    assert!(error.is_transient());
+  }
+
+  #[tokio::test]
+  async fn recv_keeps_partial_frames_when_cancelled() {
+    let (mut conn, mut server) = connected().await;
+    let frame = format!("{}\r\n\r\n", ITEM_UPDATE_IND);
+    let (first, second) = frame.as_bytes().split_at(frame.len() / 2);
+
+    server.write_all(first).await.unwrap();
+    for _ in 0..3 {
+      // the sleep stands in for an actor message winning the select in the
+      // event actor, which drops the pending receive
+      tokio::select! {
+        res = conn.recv() => panic!("received incomplete frame: {:?}", res),
+        _ = tokio::time::sleep(Duration::from_millis(20)) => (),
+      }
+    }
+
+    server.write_all(second).await.unwrap();
+    match conn.recv().await.unwrap() {
+      Response::ItemUpdate(upd) => {
+        assert_eq!(upd.values.len(), 1);
+        assert_eq!(upd.values[0].number, 16);
+      }
+      res => panic!("unexpected response: {:?}", res),
+    }
+  }
+}
@@ -0,0 +1,40 @@
+use crate::{
+  dev::{DeviceId, DeviceKind, DeviceState, DeviceValue},
+  Room,
+};
+use std::{sync::Arc, time::Instant};
+
+/// Everything the client knows about a device, see
+/// [`EnetClient::device_detail`](crate::EnetClient::device_detail).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceDetail {
+  pub id: DeviceId,
+  pub number: u32,
+  pub name: Arc<str>,
+  pub kind: DeviceKind,
+
+  /// The visible rooms the device is in, in project order.
+  pub rooms: Vec<Room>,
+
+  pub value: DeviceValue,
+
+  /// When the gateway last sent an update for the device.
+  pub last_update: Option<Instant>,
+
+  /// Whether values can be set for the device. `false` for every device of
+  /// a read-only client.
+  pub writable: bool,
+
+  /// The state a binary device was last commanded to, if the gateway
+  /// reports it. Always `None` for other devices.
+  pub setpoint: Option<DeviceState>,
+
+  /// Only set for dimmers.
+  pub dimmer: Option<DimmerDetail>,
+}
+
+/// The parts of a [`DeviceDetail`] that only dimmers have.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DimmerDetail {
+  pub transitioning: bool,
+}
@@ -1,12 +1,154 @@
-use enet_proto::{ItemUpdateValue, ProjectItem, SetValue};
-use eventuals::{Eventual, EventualReader, EventualWriter};
-use std::{cmp::Ordering, convert::TryFrom, fmt, future::ready, str::FromStr, sync::Arc};
+use enet_proto::{ClickDuration, ItemUpdateValue, ProjectItem, SetValue};
+use eventuals::{Eventual, EventualReader, EventualWriter, Value};
+use futures::stream::{self, BoxStream, StreamExt};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+  borrow::Cow,
+  cmp::Ordering,
+  convert::TryFrom,
+  fmt,
+  future::ready,
+  hash::{Hash, Hasher},
+  str::FromStr,
+  sync::{
+    atomic::{self, AtomicBool},
+    Arc,
+  },
+};
 use thiserror::Error;
 
+/// What the project says about a device, shared by the clones of it.
+///
+/// The device types compare equal when their descriptions do, so when they
+/// have the same gateway, number, name, kind and tags. The current values of
+/// the devices are not considered. They hash only the gateway and the
+/// number, which together are the stable identity of a device, see
+/// [`DeviceId`]. Clones of the same device are equal and hash the same.
+#[derive(PartialEq, Eq)]
 pub(crate) struct DeviceDesc {
-  pub name: String,
+  pub name: Arc<str>,
+  pub raw_name: String,
   pub number: u32,
   pub kind: DeviceKind,
+  pub gateway: GatewayId,
+  pub tags: Vec<String>,
+}
+
+impl DeviceDesc {
+  pub(crate) fn new(raw_name: String, number: u32, kind: DeviceKind) -> Self {
+    Self {
+      name: normalize_name(&raw_name).into(),
+      raw_name,
+      number,
+      kind,
+      gateway: GatewayId::default(),
+      tags: Vec::new(),
+    }
+  }
+
+  /// Apply metadata from the
+  /// [`metadata_provider`](crate::EnetClientBuilder::metadata_provider).
+  pub(crate) fn apply_metadata(&mut self, metadata: DeviceMetadataOverride) {
+    if let Some(name) = metadata.name {
+      self.name = normalize_name(&name).into();
+    }
+
+    self.tags = metadata.tags;
+  }
+}
+
+/// Metadata for a device from outside the gateway project, see
+/// [`EnetClientBuilder::metadata_provider`](crate::EnetClientBuilder::metadata_provider).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeviceMetadataOverride {
+  /// Replaces the name from the project. The name is normalized the same
+  /// way as the project names.
+  pub name: Option<String>,
+
+  /// Opaque tags, available from [`EnetDevice::tags`].
+  pub tags: Vec<String>,
+}
+
+/// Identifies a gateway, for telling apart devices of several gateways.
+///
+/// Set with [`EnetClientBuilder::gateway_id`](crate::EnetClientBuilder::gateway_id).
+/// Clients that don't set one all use [`GatewayId::default`], which is
+/// `"default"`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct GatewayId(Arc<str>);
+
+impl GatewayId {
+  pub fn new(id: impl Into<Arc<str>>) -> Self {
+    Self(id.into())
+  }
+
+  #[inline]
+  pub fn as_str(&self) -> &str {
+    &self.0
+  }
+}
+
+impl Default for GatewayId {
+  fn default() -> Self {
+    Self::new("default")
+  }
+}
+
+impl fmt::Display for GatewayId {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(&self.0)
+  }
+}
+
+impl From<&str> for GatewayId {
+  fn from(id: &str) -> Self {
+    Self::new(id)
+  }
+}
+
+impl From<String> for GatewayId {
+  fn from(id: String) -> Self {
+    Self::new(id)
+  }
+}
+
+impl Serialize for GatewayId {
+  fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+  where
+    S: Serializer,
+  {
+    serializer.serialize_str(&self.0)
+  }
+}
+
+impl<'de> Deserialize<'de> for GatewayId {
+  fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+  where
+    D: Deserializer<'de>,
+  {
+    String::deserialize(deserializer).map(GatewayId::from)
+  }
+}
+
+/// The identity of a device across gateways: the gateway it belongs to and
+/// its item number on that gateway.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct DeviceId {
+  pub gateway: GatewayId,
+  pub number: u32,
+}
+
+impl fmt::Display for DeviceId {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}/{}", self.gateway, self.number)
+  }
+}
+
+/// Strip control characters, then leading and trailing whitespace, from a
+/// device name.
+pub(crate) fn normalize_name(name: &str) -> String {
+  let name: String = name.chars().filter(|c| !c.is_control()).collect();
+  name.trim().to_owned()
 }
 
 impl TryFrom<ProjectItem> for DeviceDesc {
@@ -14,23 +156,11 @@ impl TryFrom<ProjectItem> for DeviceDesc {
 
   fn try_from(value: ProjectItem) -> Result<Self, Self::Error> {
     match value {
-      ProjectItem::Binaer(v) if v.programmable => Ok(DeviceDesc {
-        name: v.name,
-        number: v.number,
-        kind: DeviceKind::Binary,
-      }),
-
-      ProjectItem::Dimmer(v) => Ok(DeviceDesc {
-        name: v.name,
-        number: v.number,
-        kind: DeviceKind::Dimmer,
-      }),
-
-      ProjectItem::Jalousie(v) => Ok(DeviceDesc {
-        name: v.name,
-        number: v.number,
-        kind: DeviceKind::Blinds,
-      }),
+      ProjectItem::Binaer(v) if v.programmable => {
+        Ok(DeviceDesc::new(v.name, v.number, DeviceKind::Binary))
+      }
+      ProjectItem::Dimmer(v) => Ok(DeviceDesc::new(v.name, v.number, DeviceKind::Dimmer)),
+      ProjectItem::Jalousie(v) => Ok(DeviceDesc::new(v.name, v.number, DeviceKind::Blinds)),
 
       _ => Err(value),
     }
@@ -45,6 +175,66 @@ pub enum DeviceKind {
   Blinds,
 }
 
+impl DeviceKind {
+  const ALL: [DeviceKind; 3] = [DeviceKind::Binary, DeviceKind::Dimmer, DeviceKind::Blinds];
+  const CODES: &'static [&'static str] = &["binary", "dimmer", "blinds"];
+
+  /// A stable code for the kind, for use in serialized data. Unlike the
+  /// variant names, the codes are part of the public API and will not change.
+  pub const fn as_code(&self) -> &'static str {
+    match self {
+      DeviceKind::Binary => "binary",
+      DeviceKind::Dimmer => "dimmer",
+      DeviceKind::Blinds => "blinds",
+    }
+  }
+
+  /// The kind with the given [`as_code`](Self::as_code) code. The match is
+  /// exact, see the [`FromStr`] impl for a case-insensitive parse.
+  pub fn from_code(code: &str) -> Option<Self> {
+    Self::ALL.into_iter().find(|kind| kind.as_code() == code)
+  }
+}
+
+impl fmt::Display for DeviceKind {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(self.as_code())
+  }
+}
+
+/// Serialized as the [`as_code`](DeviceKind::as_code) code.
+impl Serialize for DeviceKind {
+  fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+  where
+    S: Serializer,
+  {
+    serializer.serialize_str(self.as_code())
+  }
+}
+
+impl<'de> Deserialize<'de> for DeviceKind {
+  fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+  where
+    D: Deserializer<'de>,
+  {
+    let code = Cow::<'de, str>::deserialize(deserializer)?;
+    DeviceKind::from_code(&code).ok_or_else(|| de::Error::unknown_variant(&code, Self::CODES))
+  }
+}
+
+/// Parses the names written by the [`Display`](fmt::Display) impl, ignoring
+/// ASCII case.
+impl FromStr for DeviceKind {
+  type Err = ParseDeviceKindError;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    DeviceKind::ALL
+      .into_iter()
+      .find(|kind| kind.as_code().eq_ignore_ascii_case(s))
+      .ok_or(ParseDeviceKindError)
+  }
+}
+
 #[non_exhaustive]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum DeviceValue {
@@ -53,6 +243,7 @@ pub enum DeviceValue {
   On(DeviceBrightness),
   AllOff,
   AllOn,
+  Blinds(BlindsPosition),
 }
 
 impl From<DeviceState> for DeviceValue {
@@ -76,6 +267,51 @@ impl From<(DeviceState, DeviceBrightness)> for DeviceValue {
 }
 
 impl DeviceValue {
+  /// [`DeviceValue::Undefined`], for building values outside of this crate.
+  #[inline]
+  pub const fn undefined() -> Self {
+    DeviceValue::Undefined
+  }
+
+  #[inline]
+  pub const fn off() -> Self {
+    DeviceValue::Off
+  }
+
+  #[inline]
+  pub const fn on(brightness: DeviceBrightness) -> Self {
+    DeviceValue::On(brightness)
+  }
+
+  #[inline]
+  pub const fn all_off() -> Self {
+    DeviceValue::AllOff
+  }
+
+  #[inline]
+  pub const fn all_on() -> Self {
+    DeviceValue::AllOn
+  }
+
+  #[inline]
+  pub const fn blinds(position: BlindsPosition) -> Self {
+    DeviceValue::Blinds(position)
+  }
+
+  /// The name of the variant, in snake case. Unlike the
+  /// [`Display`](fmt::Display) impl, this does not include the brightness or
+  /// position.
+  pub const fn as_str(&self) -> &'static str {
+    match self {
+      DeviceValue::Undefined => "undefined",
+      DeviceValue::Off => "off",
+      DeviceValue::On(_) => "on",
+      DeviceValue::AllOff => "all_off",
+      DeviceValue::AllOn => "all_on",
+      DeviceValue::Blinds(_) => "blinds",
+    }
+  }
+
   pub fn is_on(&self) -> bool {
     matches!(self, DeviceValue::On(_) | DeviceValue::AllOn)
   }
@@ -97,6 +333,8 @@ impl fmt::Display for DeviceValue {
       DeviceValue::On(v) => fmt::Display::fmt(v, f),
       DeviceValue::AllOff => f.write_str("all off"),
       DeviceValue::AllOn => f.write_str("all on"),
+      // the gateway does not report which way the blinds are moving
+      DeviceValue::Blinds(v) => write!(f, "{}%", v),
     }
   }
 }
@@ -115,8 +353,9 @@ impl TryFrom<ItemUpdateValue> for DeviceValue {
     match &*value.state {
       "UNDEFINED" => Ok(DeviceValue::Undefined),
       "OFF" => Ok(DeviceValue::Off),
-      "ON" => match DeviceBrightness::from_str(&value.value) {
-        Ok(v) => Ok(DeviceValue::On(v)),
+      "ON" => match parse_level(&value.value) {
+        Ok(Some(v)) => Ok(DeviceValue::On(v)),
+        Ok(None) => Ok(DeviceValue::Undefined),
         Err(_) => Err(value),
       },
       "ALL_OFF" => Ok(DeviceValue::AllOff),
@@ -132,6 +371,9 @@ impl PartialOrd for DeviceValue {
       (DeviceValue::Undefined, DeviceValue::Undefined) => Some(Ordering::Equal),
       (DeviceValue::Undefined, _) => None,
       (_, DeviceValue::Undefined) => None,
+      (DeviceValue::Blinds(lhs), DeviceValue::Blinds(rhs)) => Some(lhs.cmp(rhs)),
+      (DeviceValue::Blinds(_), _) => None,
+      (_, DeviceValue::Blinds(_)) => None,
       (DeviceValue::Off, DeviceValue::Off) => Some(Ordering::Equal),
       (DeviceValue::Off, DeviceValue::On(_)) => Some(Ordering::Less),
       (DeviceValue::Off, DeviceValue::AllOff) => None,
@@ -152,10 +394,62 @@ impl PartialOrd for DeviceValue {
   }
 }
 
+/// The command that reproduces a value, for restoring saved values.
+///
+/// `On` maps to `Dimm` with the same brightness, which is what dimmers need.
+/// Use [`Device::restoring_value`] to get `On` for binary devices instead.
+/// Undefined and group values can't be reproduced and are returned as the
+/// error.
+impl TryFrom<DeviceValue> for SetValue {
+  type Error = DeviceValue;
+
+  fn try_from(value: DeviceValue) -> Result<Self, Self::Error> {
+    match value {
+      DeviceValue::Off => Ok(SetValue::Off(ClickDuration::Short)),
+      DeviceValue::On(brightness) => Ok(SetValue::Dimm(brightness.get())),
+      DeviceValue::Blinds(position) => Ok(SetValue::Blinds(position.get())),
+      DeviceValue::Undefined | DeviceValue::AllOff | DeviceValue::AllOn => Err(value),
+    }
+  }
+}
+
+/// Writes to an [`Eventual`], optionally skipping values that are equal to
+/// the last one written.
+///
+/// The gateway repeats every value when the event connection signs in again,
+/// which would otherwise wake every subscriber with the value it already has.
+/// Skipping a write leaves the eventual at the same value, so
+/// [`EnetDevice::value`] is unaffected.
+pub(crate) struct ValueWriter<T: Value> {
+  writer: EventualWriter<T>,
+  last: Option<T>,
+  pub(crate) deduplicate: bool,
+}
+
+impl<T: Value> ValueWriter<T> {
+  fn new(writer: EventualWriter<T>) -> Self {
+    Self {
+      writer,
+      last: None,
+      deduplicate: true,
+    }
+  }
+
+  pub(crate) fn write(&mut self, value: T) {
+    if self.deduplicate && self.last.as_ref() == Some(&value) {
+      return;
+    }
+
+    self.last = Some(value.clone());
+    self.writer.write(value);
+  }
+}
+
 pub(crate) struct BinaryDeviceWriter {
   pub(crate) index: u32,
   pub(crate) desc: Arc<DeviceDesc>,
-  pub(crate) state_writer: EventualWriter<DeviceState>,
+  pub(crate) state_writer: ValueWriter<DeviceState>,
+  pub(crate) setpoint_writer: ValueWriter<Option<DeviceState>>,
 }
 
 impl BinaryDeviceWriter {
@@ -175,8 +469,10 @@ impl BinaryDeviceWriter {
 pub(crate) struct DimmerDeviceWriter {
   pub(crate) index: u32,
   pub(crate) desc: Arc<DeviceDesc>,
-  pub(crate) state_writer: EventualWriter<DeviceState>,
-  pub(crate) brightness_writer: EventualWriter<DeviceBrightness>,
+  pub(crate) state_writer: ValueWriter<DeviceState>,
+  pub(crate) brightness_writer: ValueWriter<DeviceBrightness>,
+  pub(crate) raw_value_writer: ValueWriter<String>,
+  transitioning: Arc<AtomicBool>,
 }
 
 impl DimmerDeviceWriter {
@@ -184,6 +480,39 @@ impl DimmerDeviceWriter {
     &*self.desc
   }
 
+  /// Record the brightness and setpoint of the latest update, see
+  /// [`DimmerDevice::is_transitioning`].
+  pub(crate) fn set_target(
+    &self,
+    brightness: Option<DeviceBrightness>,
+    setpoint: Option<DeviceBrightness>,
+  ) {
+    let transitioning = matches!((brightness, setpoint), (Some(b), Some(s)) if b != s);
+    self
+      .transitioning
+      .store(transitioning, atomic::Ordering::Relaxed);
+  }
+
+  pub(crate) fn kind(&self) -> DeviceKind {
+    self.desc().kind
+  }
+
+  pub(crate) fn name(&self) -> &str {
+    &*self.desc().name
+  }
+}
+
+pub(crate) struct BlindsDeviceWriter {
+  pub(crate) index: u32,
+  pub(crate) desc: Arc<DeviceDesc>,
+  pub(crate) position_writer: ValueWriter<Option<BlindsPosition>>,
+}
+
+impl BlindsDeviceWriter {
+  fn desc(&self) -> &DeviceDesc {
+    &*self.desc
+  }
+
   pub(crate) fn kind(&self) -> DeviceKind {
     self.desc().kind
   }
@@ -196,6 +525,7 @@ impl DimmerDeviceWriter {
 pub(crate) enum DeviceWriter {
   Binary(BinaryDeviceWriter),
   Dimmer(DimmerDeviceWriter),
+  Blinds(BlindsDeviceWriter),
 }
 
 impl DeviceWriter {
@@ -203,11 +533,13 @@ impl DeviceWriter {
     desc: Arc<DeviceDesc>,
     index: u32,
     state_writer: EventualWriter<DeviceState>,
+    setpoint_writer: EventualWriter<Option<DeviceState>>,
   ) -> Self {
     DeviceWriter::Binary(BinaryDeviceWriter {
       index,
       desc,
-      state_writer,
+      state_writer: ValueWriter::new(state_writer),
+      setpoint_writer: ValueWriter::new(setpoint_writer),
     })
   }
 
@@ -216,12 +548,28 @@ impl DeviceWriter {
     index: u32,
     state_writer: EventualWriter<DeviceState>,
     brightness_writer: EventualWriter<DeviceBrightness>,
+    raw_value_writer: EventualWriter<String>,
+    transitioning: Arc<AtomicBool>,
   ) -> Self {
     DeviceWriter::Dimmer(DimmerDeviceWriter {
       index,
       desc,
-      state_writer,
-      brightness_writer,
+      state_writer: ValueWriter::new(state_writer),
+      brightness_writer: ValueWriter::new(brightness_writer),
+      raw_value_writer: ValueWriter::new(raw_value_writer),
+      transitioning,
+    })
+  }
+
+  fn new_blinds(
+    desc: Arc<DeviceDesc>,
+    index: u32,
+    position_writer: EventualWriter<Option<BlindsPosition>>,
+  ) -> Self {
+    DeviceWriter::Blinds(BlindsDeviceWriter {
+      index,
+      desc,
+      position_writer: ValueWriter::new(position_writer),
     })
   }
 
@@ -229,6 +577,24 @@ impl DeviceWriter {
     match self {
       DeviceWriter::Binary(w) => w.index,
       DeviceWriter::Dimmer(w) => w.index,
+      DeviceWriter::Blinds(w) => w.index,
+    }
+  }
+
+  /// Whether writes of a value equal to the last written one are skipped, so
+  /// subscribers only see actual changes. On by default.
+  pub(crate) fn set_deduplicate(&mut self, deduplicate: bool) {
+    match self {
+      DeviceWriter::Binary(w) => {
+        w.state_writer.deduplicate = deduplicate;
+        w.setpoint_writer.deduplicate = deduplicate;
+      }
+      DeviceWriter::Dimmer(w) => {
+        w.state_writer.deduplicate = deduplicate;
+        w.brightness_writer.deduplicate = deduplicate;
+        w.raw_value_writer.deduplicate = deduplicate;
+      }
+      DeviceWriter::Blinds(w) => w.position_writer.deduplicate = deduplicate,
     }
   }
 
@@ -236,6 +602,7 @@ impl DeviceWriter {
     match self {
       DeviceWriter::Binary(w) => &*w.desc,
       DeviceWriter::Dimmer(w) => &*w.desc,
+      DeviceWriter::Blinds(w) => &*w.desc,
     }
   }
 
@@ -255,14 +622,18 @@ pub enum DeviceState {
   Unknown,
 }
 
-impl From<SetValue> for DeviceState {
-  fn from(v: SetValue) -> Self {
-    match v {
-      SetValue::On(_) => Self::On,
-      SetValue::Off(_) => Self::Off,
-      SetValue::Dimm(0) => Self::Off,
-      SetValue::Dimm(_) => Self::On,
-      SetValue::Blinds(_) => todo!(),
+/// The state a command puts a device in. A [`SetValue::Blinds`] position has
+/// no on/off state and is returned as the error.
+impl TryFrom<SetValue> for DeviceState {
+  type Error = SetValue;
+
+  fn try_from(value: SetValue) -> Result<Self, Self::Error> {
+    match value {
+      SetValue::On(_) => Ok(Self::On),
+      SetValue::Off(_) => Ok(Self::Off),
+      SetValue::Dimm(0) => Ok(Self::Off),
+      SetValue::Dimm(_) => Ok(Self::On),
+      SetValue::Blinds(_) => Err(value),
     }
   }
 }
@@ -290,6 +661,15 @@ impl FromStr for DeviceState {
   }
 }
 
+/// Parse the `SETPOINT` field of an update for a binary device. Empty and
+/// unrecognized setpoints are reported as `None`.
+pub(crate) fn parse_setpoint(s: &str) -> Option<DeviceState> {
+  match DeviceState::from_str(s) {
+    Ok(DeviceState::Unknown) | Err(_) => None,
+    Ok(state) => Some(state),
+  }
+}
+
 #[repr(transparent)]
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct DeviceBrightness(u8);
@@ -310,6 +690,13 @@ impl DeviceBrightness {
   pub const fn get(self) -> u8 {
     self.0
   }
+
+  /// Add `delta` percent, staying within [`MIN`](Self::MIN) and
+  /// [`MAX`](Self::MAX).
+  pub fn saturating_add_signed(self, delta: i8) -> Self {
+    let value = i16::from(self.0) + i16::from(delta);
+    Self(value.clamp(0, 100) as u8)
+  }
 }
 
 impl fmt::Debug for DeviceBrightness {
@@ -324,31 +711,97 @@ impl fmt::Display for DeviceBrightness {
   }
 }
 
+/// Parses a brightness in percent, such as `75` or `75%`.
 impl FromStr for DeviceBrightness {
   type Err = ParseDeviceBrightnessError;
 
   fn from_str(s: &str) -> Result<Self, Self::Err> {
-    if s.len() > 3 || s.is_empty() {
-      return Err(ParseDeviceBrightnessError);
+    let s = s.strip_suffix('%').unwrap_or(s);
+    if s.is_empty() {
+      return Err(ParseDeviceBrightnessError::Empty);
+    }
+
+    if s.len() > 3 {
+      return Err(ParseDeviceBrightnessError::TooLong);
     }
 
-    let mut v = 0u8;
+    let mut v = 0u16;
     for b in s.bytes() {
       if !(b'0'..=b'9').contains(&b) {
-        return Err(ParseDeviceBrightnessError);
+        return Err(ParseDeviceBrightnessError::NotANumber);
       }
 
       v *= 10;
-      v += b - b'0';
+      v += (b - b'0') as u16;
     }
 
-    match DeviceBrightness::new(v) {
-      None => Err(ParseDeviceBrightnessError),
+    match u8::try_from(v).ok().and_then(DeviceBrightness::new) {
+      None => Err(ParseDeviceBrightnessError::OutOfRange(v)),
       Some(v) => Ok(v),
     }
   }
 }
 
+/// Parses a level field (`VALUE`/`SETPOINT`), such as a brightness or a blinds
+/// position, as reported by the gateway.
+///
+/// The gateway reports `-1` (and occasionally an empty string) when it has no
+/// known level for a channel, for instance right after boot or when the
+/// actuator is unreachable. This is returned as `Ok(None)` so callers can treat
+/// the device as undefined rather than as being at level 0.
+pub(crate) fn parse_level<T: FromStr>(s: &str) -> Result<Option<T>, T::Err> {
+  match s {
+    "" | "-1" => Ok(None),
+    s => T::from_str(s).map(Some),
+  }
+}
+
+/// Position of a blinds device, in percent.
+#[repr(transparent)]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BlindsPosition(u8);
+
+impl BlindsPosition {
+  pub const MIN: BlindsPosition = BlindsPosition(0);
+  pub const MAX: BlindsPosition = BlindsPosition(100);
+
+  pub const fn new(value: u8) -> Option<Self> {
+    if value <= 100 {
+      Some(Self(value))
+    } else {
+      None
+    }
+  }
+
+  #[inline]
+  pub const fn get(self) -> u8 {
+    self.0
+  }
+}
+
+impl fmt::Debug for BlindsPosition {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    fmt::Debug::fmt(&self.0, f)
+  }
+}
+
+impl fmt::Display for BlindsPosition {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    fmt::Display::fmt(&self.0, f)
+  }
+}
+
+impl FromStr for BlindsPosition {
+  type Err = ParseBlindsPositionError;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match DeviceBrightness::from_str(s) {
+      Ok(v) => Ok(Self(v.get())),
+      Err(_) => Err(ParseBlindsPositionError),
+    }
+  }
+}
+
 #[derive(Clone, Copy, PartialEq, Eq, Hash)]
 pub enum DeviceGroupState {
   AllOff,
@@ -356,26 +809,110 @@ pub enum DeviceGroupState {
 }
 
 pub trait EnetDevice {
+  /// The name of the device, with control characters and surrounding
+  /// whitespace removed. This is the name from the
+  /// [`metadata_provider`](crate::EnetClientBuilder::metadata_provider) if it
+  /// supplied one.
   fn name(&self) -> &str;
+
+  /// Like [`name`](Self::name), as a reference counted string that is cheap
+  /// to clone and keep around.
+  fn shared_name(&self) -> Arc<str>;
+
+  /// The name of the device exactly as configured in the project.
+  fn raw_name(&self) -> &str;
+
+  /// The tags from the
+  /// [`metadata_provider`](crate::EnetClientBuilder::metadata_provider), if
+  /// any. The default has none.
+  fn tags(&self) -> &[String] {
+    &[]
+  }
+
   fn number(&self) -> u32;
+
+  /// The number of the device together with the gateway it belongs to, see
+  /// [`DeviceId`].
+  ///
+  /// The default uses [`GatewayId::default`], which is what a client without
+  /// a [`gateway_id`](crate::EnetClientBuilder::gateway_id) uses.
+  fn id(&self) -> DeviceId {
+    DeviceId {
+      gateway: GatewayId::default(),
+      number: self.number(),
+    }
+  }
+
   fn kind(&self) -> DeviceKind;
   fn subscribe(&self) -> EventualReader<DeviceValue>;
+
+  /// The latest known value of the device, without waiting for an update.
+  fn value(&self) -> DeviceValue;
+
+  /// Like [`subscribe`](Self::subscribe), as a [`Stream`](futures::Stream).
+  /// The stream ends when the client is dropped.
+  fn value_stream(&self) -> BoxStream<'static, DeviceValue> {
+    stream::unfold(self.subscribe(), |mut reader| async move {
+      match reader.next().await {
+        Ok(value) => Some((value, reader)),
+        Err(_) => None,
+      }
+    })
+    .boxed()
+  }
 }
 
 #[derive(Clone)]
 pub struct BinaryDevice {
   pub(crate) desc: Arc<DeviceDesc>,
   pub(crate) state: Eventual<DeviceState>,
+  pub(crate) setpoint: Eventual<Option<DeviceState>>,
+}
+
+/// Compares the descriptions of the devices, see `DeviceDesc`.
+impl PartialEq for BinaryDevice {
+  #[inline]
+  fn eq(&self, other: &Self) -> bool {
+    self.desc == other.desc
+  }
+}
+
+impl Eq for BinaryDevice {}
+
+/// Hashes the identity of the device, see `DeviceDesc`.
+impl Hash for BinaryDevice {
+  #[inline]
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    self.desc.gateway.hash(state);
+    self.desc.number.hash(state);
+  }
 }
 
 impl BinaryDevice {
-  fn new(desc: Arc<DeviceDesc>, state: Eventual<DeviceState>) -> Self {
-    Self { desc, state }
+  fn new(
+    desc: Arc<DeviceDesc>,
+    state: Eventual<DeviceState>,
+    setpoint: Eventual<Option<DeviceState>>,
+  ) -> Self {
+    Self {
+      desc,
+      state,
+      setpoint,
+    }
   }
 
+  /// The actual state of the device.
   pub fn subscribe_state(&self) -> EventualReader<DeviceState> {
     self.state.subscribe()
   }
+
+  /// The state the device was last commanded to. While this differs from
+  /// the actual state, the gateway has not finished applying the command.
+  ///
+  /// `None` when the gateway does not report a setpoint.
+  pub fn subscribe_setpoint(&self) -> EventualReader<Option<DeviceState>> {
+    self.setpoint.subscribe()
+  }
 }
 
 impl EnetDevice for BinaryDevice {
@@ -383,6 +920,25 @@ impl EnetDevice for BinaryDevice {
     &*self.desc.name
   }
 
+  fn shared_name(&self) -> Arc<str> {
+    self.desc.name.clone()
+  }
+
+  fn raw_name(&self) -> &str {
+    &*self.desc.raw_name
+  }
+
+  fn tags(&self) -> &[String] {
+    &self.desc.tags
+  }
+
+  fn id(&self) -> DeviceId {
+    DeviceId {
+      gateway: self.desc.gateway.clone(),
+      number: self.desc.number,
+    }
+  }
+
   fn number(&self) -> u32 {
     self.desc.number
   }
@@ -394,6 +950,14 @@ impl EnetDevice for BinaryDevice {
   fn subscribe(&self) -> EventualReader<DeviceValue> {
     eventuals::map(&self.state, |v| ready(v.into())).subscribe()
   }
+
+  fn value(&self) -> DeviceValue {
+    self
+      .state
+      .value_immediate()
+      .map(DeviceValue::from)
+      .unwrap_or_default()
+  }
 }
 
 #[derive(Clone)]
@@ -401,6 +965,27 @@ pub struct DimmerDevice {
   pub(crate) desc: Arc<DeviceDesc>,
   pub(crate) state: Eventual<DeviceState>,
   pub(crate) brightness: Eventual<DeviceBrightness>,
+  pub(crate) raw_value: Eventual<String>,
+  transitioning: Arc<AtomicBool>,
+}
+
+/// Compares the descriptions of the devices, see `DeviceDesc`.
+impl PartialEq for DimmerDevice {
+  #[inline]
+  fn eq(&self, other: &Self) -> bool {
+    self.desc == other.desc
+  }
+}
+
+impl Eq for DimmerDevice {}
+
+/// Hashes the identity of the device, see `DeviceDesc`.
+impl Hash for DimmerDevice {
+  #[inline]
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    self.desc.gateway.hash(state);
+    self.desc.number.hash(state);
+  }
 }
 
 impl DimmerDevice {
@@ -408,11 +993,15 @@ impl DimmerDevice {
     desc: Arc<DeviceDesc>,
     state: Eventual<DeviceState>,
     brightness: Eventual<DeviceBrightness>,
+    raw_value: Eventual<String>,
+    transitioning: Arc<AtomicBool>,
   ) -> Self {
     Self {
       desc,
       state,
       brightness,
+      raw_value,
+      transitioning,
     }
   }
 
@@ -423,6 +1012,25 @@ impl DimmerDevice {
   pub fn subscribe_brightness(&self) -> EventualReader<DeviceBrightness> {
     self.brightness.subscribe()
   }
+
+  /// The `VALUE` field of every update for the dimmer, as sent by the gateway.
+  ///
+  /// Unlike [`subscribe_brightness`](Self::subscribe_brightness), this also
+  /// yields values that can't be parsed as a [`DeviceBrightness`], such as the
+  /// ones some firmwares report while the dimmer is transitioning.
+  pub fn subscribe_raw_value(&self) -> EventualReader<String> {
+    self.raw_value.subscribe()
+  }
+
+  /// Whether the dimmer is ramping towards a new brightness. During a ramp
+  /// the gateway reports the intermediate brightness as the value, while the
+  /// setpoint holds the target.
+  ///
+  /// This reflects the latest update; it is `false` when the gateway reports
+  /// no setpoint for the dimmer.
+  pub fn is_transitioning(&self) -> bool {
+    self.transitioning.load(atomic::Ordering::Relaxed)
+  }
 }
 
 impl EnetDevice for DimmerDevice {
@@ -430,6 +1038,25 @@ impl EnetDevice for DimmerDevice {
     &*self.desc.name
   }
 
+  fn shared_name(&self) -> Arc<str> {
+    self.desc.name.clone()
+  }
+
+  fn raw_name(&self) -> &str {
+    &*self.desc.raw_name
+  }
+
+  fn tags(&self) -> &[String] {
+    &self.desc.tags
+  }
+
+  fn id(&self) -> DeviceId {
+    DeviceId {
+      gateway: self.desc.gateway.clone(),
+      number: self.desc.number,
+    }
+  }
+
   fn number(&self) -> u32 {
     self.desc.number
   }
@@ -443,12 +1070,116 @@ impl EnetDevice for DimmerDevice {
     let mapped = eventuals::map(joined, |v| ready(v.into()));
     mapped.subscribe()
   }
+
+  fn value(&self) -> DeviceValue {
+    match (
+      self.state.value_immediate(),
+      self.brightness.value_immediate(),
+    ) {
+      (Some(state), Some(brightness)) => (state, brightness).into(),
+      _ => DeviceValue::Undefined,
+    }
+  }
 }
 
+/// Blinds (a `Jalousie` in the project).
+///
+/// Only the travel position is reported. The gateway does not report the slat
+/// angle of venetian blinds, nor accept a command to set it.
 #[derive(Clone)]
+pub struct BlindsDevice {
+  pub(crate) desc: Arc<DeviceDesc>,
+  pub(crate) position: Eventual<Option<BlindsPosition>>,
+}
+
+/// Compares the descriptions of the devices, see `DeviceDesc`.
+impl PartialEq for BlindsDevice {
+  #[inline]
+  fn eq(&self, other: &Self) -> bool {
+    self.desc == other.desc
+  }
+}
+
+impl Eq for BlindsDevice {}
+
+/// Hashes the identity of the device, see `DeviceDesc`.
+impl Hash for BlindsDevice {
+  #[inline]
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    self.desc.gateway.hash(state);
+    self.desc.number.hash(state);
+  }
+}
+
+impl BlindsDevice {
+  fn new(desc: Arc<DeviceDesc>, position: Eventual<Option<BlindsPosition>>) -> Self {
+    Self { desc, position }
+  }
+
+  /// Subscribe to the current position of the blinds.
+  ///
+  /// The position is `None` while the gateway has no known position for the
+  /// device.
+  pub fn subscribe_position(&self) -> EventualReader<Option<BlindsPosition>> {
+    self.position.subscribe()
+  }
+}
+
+impl EnetDevice for BlindsDevice {
+  fn name(&self) -> &str {
+    &*self.desc.name
+  }
+
+  fn shared_name(&self) -> Arc<str> {
+    self.desc.name.clone()
+  }
+
+  fn raw_name(&self) -> &str {
+    &*self.desc.raw_name
+  }
+
+  fn tags(&self) -> &[String] {
+    &self.desc.tags
+  }
+
+  fn id(&self) -> DeviceId {
+    DeviceId {
+      gateway: self.desc.gateway.clone(),
+      number: self.desc.number,
+    }
+  }
+
+  fn number(&self) -> u32 {
+    self.desc.number
+  }
+
+  fn kind(&self) -> DeviceKind {
+    DeviceKind::Blinds
+  }
+
+  fn subscribe(&self) -> EventualReader<DeviceValue> {
+    eventuals::map(&self.position, |v| {
+      ready(match v {
+        Some(position) => DeviceValue::Blinds(position),
+        None => DeviceValue::Undefined,
+      })
+    })
+    .subscribe()
+  }
+
+  fn value(&self) -> DeviceValue {
+    match self.position.value_immediate().flatten() {
+      Some(position) => DeviceValue::Blinds(position),
+      None => DeviceValue::Undefined,
+    }
+  }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub enum Device {
   Binary(BinaryDevice),
   Dimmer(DimmerDevice),
+  Blinds(BlindsDevice),
 }
 
 impl EnetDevice for Device {
@@ -456,6 +1187,39 @@ impl EnetDevice for Device {
     match self {
       Device::Binary(d) => d.name(),
       Device::Dimmer(d) => d.name(),
+      Device::Blinds(d) => d.name(),
+    }
+  }
+
+  fn shared_name(&self) -> Arc<str> {
+    match self {
+      Device::Binary(d) => d.shared_name(),
+      Device::Dimmer(d) => d.shared_name(),
+      Device::Blinds(d) => d.shared_name(),
+    }
+  }
+
+  fn raw_name(&self) -> &str {
+    match self {
+      Device::Binary(d) => d.raw_name(),
+      Device::Dimmer(d) => d.raw_name(),
+      Device::Blinds(d) => d.raw_name(),
+    }
+  }
+
+  fn tags(&self) -> &[String] {
+    match self {
+      Device::Binary(d) => d.tags(),
+      Device::Dimmer(d) => d.tags(),
+      Device::Blinds(d) => d.tags(),
+    }
+  }
+
+  fn id(&self) -> DeviceId {
+    match self {
+      Device::Binary(d) => d.id(),
+      Device::Dimmer(d) => d.id(),
+      Device::Blinds(d) => d.id(),
     }
   }
 
@@ -463,6 +1227,7 @@ impl EnetDevice for Device {
     match self {
       Device::Binary(d) => d.number(),
       Device::Dimmer(d) => d.number(),
+      Device::Blinds(d) => d.number(),
     }
   }
 
@@ -470,6 +1235,7 @@ impl EnetDevice for Device {
     match self {
       Device::Binary(d) => d.kind(),
       Device::Dimmer(d) => d.kind(),
+      Device::Blinds(d) => d.kind(),
     }
   }
 
@@ -477,17 +1243,62 @@ impl EnetDevice for Device {
     match self {
       Device::Binary(d) => d.subscribe(),
       Device::Dimmer(d) => d.subscribe(),
+      Device::Blinds(d) => d.subscribe(),
+    }
+  }
+
+  fn value(&self) -> DeviceValue {
+    match self {
+      Device::Binary(d) => d.value(),
+      Device::Dimmer(d) => d.value(),
+      Device::Blinds(d) => d.value(),
     }
   }
 }
 
 impl Device {
+  /// The device as a [`BinaryDevice`], if it is one.
+  #[inline]
+  pub fn as_binary(&self) -> Option<&BinaryDevice> {
+    match self {
+      Device::Binary(device) => Some(device),
+      _ => None,
+    }
+  }
+
+  /// The device as a [`DimmerDevice`], if it is one.
+  #[inline]
+  pub fn as_dimmer(&self) -> Option<&DimmerDevice> {
+    match self {
+      Device::Dimmer(device) => Some(device),
+      _ => None,
+    }
+  }
+
+  /// The device as a [`BlindsDevice`], if it is one.
+  #[inline]
+  pub fn as_blinds(&self) -> Option<&BlindsDevice> {
+    match self {
+      Device::Blinds(device) => Some(device),
+      _ => None,
+    }
+  }
+
+  /// The command that reproduces `value` on this device. Like
+  /// `SetValue::try_from`, but turns binary devices on with a short click.
+  pub fn restoring_value(&self, value: DeviceValue) -> Result<SetValue, DeviceValue> {
+    match (self, value) {
+      (Device::Binary(_), DeviceValue::On(_)) => Ok(SetValue::On(ClickDuration::Short)),
+      _ => SetValue::try_from(value),
+    }
+  }
+
   pub(crate) fn new(desc: DeviceDesc, index: u32) -> (DeviceWriter, Self) {
     let desc = Arc::new(desc);
     match desc.kind {
       DeviceKind::Binary => Self::new_binary(desc, index),
       DeviceKind::Dimmer => Self::new_dimmer(desc, index),
-      DeviceKind::Blinds => todo!(),
+      DeviceKind::Blinds => Self::new_blinds(desc, index),
     }
   }
 
@@ -495,10 +1306,11 @@ impl Device {
     debug_assert_eq!(desc.kind, DeviceKind::Binary);
 
     let (state_writer, state) = Eventual::new();
+    let (setpoint_writer, setpoint) = Eventual::new();
 
     (
-      DeviceWriter::new_binary(desc.clone(), index, state_writer),
-      Self::Binary(BinaryDevice::new(desc, state)),
+      DeviceWriter::new_binary(desc.clone(), index, state_writer, setpoint_writer),
+      Self::Binary(BinaryDevice::new(desc, state, setpoint)),
     )
   }
 
@@ -507,20 +1319,320 @@ impl Device {
 
     let (state_writer, state) = Eventual::new();
     let (brightness_writer, brightness) = Eventual::new();
+    let (raw_value_writer, raw_value) = Eventual::new();
+    let transitioning = Arc::new(AtomicBool::new(false));
+
+    (
+      DeviceWriter::new_dimmer(
+        desc.clone(),
+        index,
+        state_writer,
+        brightness_writer,
+        raw_value_writer,
+        transitioning.clone(),
+      ),
+      Self::Dimmer(DimmerDevice::new(
+        desc,
+        state,
+        brightness,
+        raw_value,
+        transitioning,
+      )),
+    )
+  }
+
+  fn new_blinds(desc: Arc<DeviceDesc>, index: u32) -> (DeviceWriter, Self) {
+    debug_assert_eq!(desc.kind, DeviceKind::Blinds);
+
+    let (position_writer, position) = Eventual::new();
 
     (
-      DeviceWriter::new_dimmer(desc.clone(), index, state_writer, brightness_writer),
-      Self::Dimmer(DimmerDevice::new(desc, state, brightness)),
+      DeviceWriter::new_blinds(desc.clone(), index, position_writer),
+      Self::Blinds(BlindsDevice::new(desc, position)),
     )
   }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+#[non_exhaustive]
+pub enum ParseDeviceBrightnessError {
+  #[error("Failed to parse 'on' value. Value is empty.")]
+  Empty,
+
+  #[error("Failed to parse 'on' value. Must be at most 3 digits.")]
+  TooLong,
+
+  #[error("Failed to parse 'on' value. Must only contain digits.")]
+  NotANumber,
+
+  #[error("Failed to parse 'on' value. Must be 0..=100, got {0}.")]
+  OutOfRange(u16),
+}
+
 #[derive(Debug, Error)]
 #[non_exhaustive]
-#[error("Failed to parse 'on' value. Must be 0..=100.")]
-pub struct ParseDeviceBrightnessError;
+#[error("Failed to parse blinds position. Must be 0..=100.")]
+pub struct ParseBlindsPositionError;
 
 #[derive(Debug, Error)]
 #[non_exhaustive]
 #[error("Failed to parse state. Must be either 'ON' or 'OFF'.")]
 pub struct ParseDeviceStateError;
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+#[error("Failed to parse device kind. Must be one of 'binary', 'dimmer' or 'blinds'.")]
+pub struct ParseDeviceKindError;
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::collections::HashSet;
+
+  #[test]
+  fn device_names_are_normalized() {
+    let desc = DeviceDesc::new(" Living room\r".into(), 5, DeviceKind::Binary);
+    assert_eq!(&*desc.name, "Living room");
+    assert_eq!(desc.raw_name, " Living room\r");
+
+    let desc = DeviceDesc::new("Bath\u{7}room".into(), 6, DeviceKind::Binary);
+    assert_eq!(&*desc.name, "Bathroom");
+  }
+
+  #[test]
+  fn device_kind_round_trips() {
+    for kind in [DeviceKind::Binary, DeviceKind::Dimmer, DeviceKind::Blinds] {
+      assert_eq!(kind.to_string().parse::<DeviceKind>().unwrap(), kind);
+    }
+
+    assert_eq!("Dimmer".parse::<DeviceKind>().unwrap(), DeviceKind::Dimmer);
+    assert!("scene".parse::<DeviceKind>().is_err());
+  }
+
+  #[test]
+  fn device_kind_codes_are_stable() {
+    assert_eq!(DeviceKind::Binary.as_code(), "binary");
+    assert_eq!(DeviceKind::Dimmer.as_code(), "dimmer");
+    assert_eq!(DeviceKind::Blinds.as_code(), "blinds");
+    for (kind, code) in DeviceKind::ALL.into_iter().zip(DeviceKind::CODES) {
+      assert_eq!(kind.as_code(), *code);
+      assert_eq!(DeviceKind::from_code(code), Some(kind));
+    }
+
+    assert_eq!(DeviceKind::from_code("Dimmer"), None);
+    assert_eq!(DeviceKind::from_code("jalousie"), None);
+  }
+
+  #[test]
+  fn device_value_constructors() {
+    assert_eq!(DeviceValue::undefined(), DeviceValue::Undefined);
+    assert_eq!(DeviceValue::off(), DeviceValue::Off);
+    assert_eq!(
+      DeviceValue::on(DeviceBrightness(30)),
+      DeviceValue::On(DeviceBrightness(30))
+    );
+    assert_eq!(DeviceValue::all_on().as_str(), "all_on");
+    assert_eq!(DeviceValue::on(DeviceBrightness::MAX).as_str(), "on");
+    assert_eq!(DeviceValue::blinds(BlindsPosition::MIN).as_str(), "blinds");
+  }
+
+  #[test]
+  fn unknown_levels_parse_as_none() {
+    assert_eq!(parse_level::<DeviceBrightness>("-1"), Ok(None));
+    assert_eq!(parse_level::<DeviceBrightness>(""), Ok(None));
+    assert_eq!(
+      parse_level::<DeviceBrightness>("0"),
+      Ok(DeviceBrightness::new(0))
+    );
+    assert_eq!(
+      parse_level::<DeviceBrightness>("100"),
+      Ok(DeviceBrightness::new(100))
+    );
+    assert!(parse_level::<DeviceBrightness>("abc").is_err());
+  }
+
+  #[test]
+  fn blinds_value_display() {
+    let value = DeviceValue::Blinds(BlindsPosition::new(40).unwrap());
+    assert_eq!(value.to_string(), "40%");
+    assert_eq!(DeviceValue::Blinds(BlindsPosition::MAX).to_string(), "100%");
+  }
+
+  #[test]
+  fn blinds_value_ordering() {
+    let low = DeviceValue::Blinds(BlindsPosition::new(10).unwrap());
+    let high = DeviceValue::Blinds(BlindsPosition::new(90).unwrap());
+
+    assert_eq!(low.partial_cmp(&high), Some(Ordering::Less));
+    assert_eq!(high.partial_cmp(&low), Some(Ordering::Greater));
+    assert_eq!(low.partial_cmp(&low), Some(Ordering::Equal));
+    assert_eq!(low.partial_cmp(&DeviceValue::Off), None);
+    assert_eq!(
+      DeviceValue::On(DeviceBrightness::MAX).partial_cmp(&high),
+      None
+    );
+    assert_eq!(low.partial_cmp(&DeviceValue::Undefined), None);
+  }
+
+  #[test]
+  fn set_value_from_device_value() {
+    let brightness = DeviceBrightness::new(30).unwrap();
+    assert!(matches!(
+      SetValue::try_from(DeviceValue::On(brightness)),
+      Ok(SetValue::Dimm(30))
+    ));
+    assert!(matches!(
+      SetValue::try_from(DeviceValue::Off),
+      Ok(SetValue::Off(ClickDuration::Short))
+    ));
+    assert_eq!(
+      SetValue::try_from(DeviceValue::AllOn).unwrap_err(),
+      DeviceValue::AllOn
+    );
+
+    let desc = DeviceDesc::new("Porch".into(), 2, DeviceKind::Binary);
+    let (_writer, device) = Device::new(desc, 2);
+    assert!(matches!(
+      device.restoring_value(DeviceValue::On(DeviceBrightness::MAX)),
+      Ok(SetValue::On(ClickDuration::Short))
+    ));
+  }
+
+  #[test]
+  fn device_ids_include_the_gateway() {
+    let desc = DeviceDesc::new("Hallway".into(), 3, DeviceKind::Dimmer);
+    let (_writer, device) = Device::new(desc, 3);
+    assert_eq!(
+      device.id(),
+      DeviceId {
+        gateway: GatewayId::default(),
+        number: 3,
+      }
+    );
+    assert_eq!(device.id().to_string(), "default/3");
+
+    let mut desc = DeviceDesc::new("Hallway".into(), 3, DeviceKind::Dimmer);
+    desc.gateway = GatewayId::new("upstairs");
+    let (_writer, other) = Device::new(desc, 3);
+    assert_ne!(device.id(), other.id());
+
+    // the same device on another gateway is another device
+    assert!(device != other);
+    let set: HashSet<_> = vec![device, other].into_iter().collect();
+    assert_eq!(set.len(), 2);
+  }
+
+  #[test]
+  fn device_downcasts() {
+    let desc = DeviceDesc::new("Hallway".into(), 3, DeviceKind::Dimmer);
+    let (_writer, device) = Device::new(desc, 3);
+    assert_eq!(device.as_dimmer().map(|d| d.number()), Some(3));
+    assert!(device.as_binary().is_none());
+    assert!(device.as_blinds().is_none());
+  }
+
+  #[test]
+  fn dimmer_transitions_while_value_differs_from_setpoint() {
+    let desc = DeviceDesc::new("Hallway".into(), 3, DeviceKind::Dimmer);
+    let (writer, device) = Device::new(desc, 3);
+    let (writer, device) = match (writer, device) {
+      (DeviceWriter::Dimmer(w), Device::Dimmer(d)) => (w, d),
+      _ => unreachable!(),
+    };
+    assert!(!device.is_transitioning());
+
+    writer.set_target(DeviceBrightness::new(20), DeviceBrightness::new(80));
+    assert!(device.is_transitioning());
+
+    writer.set_target(DeviceBrightness::new(80), DeviceBrightness::new(80));
+    assert!(!device.is_transitioning());
+
+    writer.set_target(DeviceBrightness::new(20), None);
+    assert!(!device.is_transitioning());
+  }
+
+  #[test]
+  fn binary_setpoint() {
+    assert_eq!(parse_setpoint("ON"), Some(DeviceState::On));
+    assert_eq!(parse_setpoint("OFF"), Some(DeviceState::Off));
+    assert_eq!(parse_setpoint(""), None);
+    assert_eq!(parse_setpoint("UNKNOWN"), None);
+    assert_eq!(parse_setpoint("255"), None);
+  }
+
+  #[tokio::test]
+  async fn repeated_values_are_not_emitted() {
+    let desc = DeviceDesc::new("Hallway".into(), 4, DeviceKind::Binary);
+    let (writer, device) = Device::new(desc, 4);
+    let mut writer = match writer {
+      DeviceWriter::Binary(w) => w,
+      _ => unreachable!(),
+    };
+    let mut reader = device.subscribe();
+
+    writer.state_writer.write(DeviceState::On);
+    assert_eq!(
+      reader.next().await.unwrap(),
+      DeviceValue::On(DeviceBrightness::MAX)
+    );
+
+    writer.state_writer.write(DeviceState::On);
+    let repeated = tokio::time::timeout(std::time::Duration::from_millis(50), reader.next());
+    assert!(repeated.await.is_err());
+    assert_eq!(device.value(), DeviceValue::On(DeviceBrightness::MAX));
+
+    writer.state_writer.write(DeviceState::Off);
+    assert_eq!(reader.next().await.unwrap(), DeviceValue::Off);
+  }
+
+  #[test]
+  fn cloned_devices_are_the_same_map_key() {
+    let desc = DeviceDesc::new("Kitchen".into(), 16, DeviceKind::Dimmer);
+    let (_writer, device) = Device::new(desc, 16);
+
+    let mut set = HashSet::new();
+    set.insert(device.clone());
+    assert!(set.contains(&device));
+  }
+
+  #[test]
+  fn device_brightness_saturating_add_signed() {
+    let brightness = DeviceBrightness(50);
+    assert_eq!(brightness.saturating_add_signed(10), DeviceBrightness(60));
+    assert_eq!(brightness.saturating_add_signed(-20), DeviceBrightness(30));
+    assert_eq!(
+      brightness.saturating_add_signed(i8::MAX),
+      DeviceBrightness::MAX
+    );
+    assert_eq!(
+      brightness.saturating_add_signed(i8::MIN),
+      DeviceBrightness::MIN
+    );
+  }
+
+  #[test]
+  fn device_brightness_from_str() {
+    assert_eq!("0".parse(), Ok(DeviceBrightness::MIN));
+    assert_eq!("42".parse(), Ok(DeviceBrightness(42)));
+    assert_eq!("100".parse(), Ok(DeviceBrightness::MAX));
+    assert_eq!("75%".parse(), Ok(DeviceBrightness(75)));
+    assert_eq!("100%".parse(), Ok(DeviceBrightness::MAX));
+  }
+
+  #[test]
+  fn device_brightness_from_str_errors() {
+    use ParseDeviceBrightnessError::*;
+
+    assert_eq!("".parse::<DeviceBrightness>(), Err(Empty));
+    assert_eq!("1000".parse::<DeviceBrightness>(), Err(TooLong));
+    assert_eq!("4a".parse::<DeviceBrightness>(), Err(NotANumber));
+    assert_eq!("-1".parse::<DeviceBrightness>(), Err(NotANumber));
+    assert_eq!("101".parse::<DeviceBrightness>(), Err(OutOfRange(101)));
+    assert_eq!("999".parse::<DeviceBrightness>(), Err(OutOfRange(999)));
+    assert_eq!("%".parse::<DeviceBrightness>(), Err(Empty));
+    assert_eq!("%75".parse::<DeviceBrightness>(), Err(NotANumber));
+    assert_eq!("7%5".parse::<DeviceBrightness>(), Err(NotANumber));
+    assert_eq!("75%%".parse::<DeviceBrightness>(), Err(NotANumber));
+    assert_eq!("101%".parse::<DeviceBrightness>(), Err(OutOfRange(101)));
+  }
+}
@@ -0,0 +1,167 @@
+use crate::dev::{normalize_name, Device, DeviceKind, EnetDevice};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// A device as declared in a configuration, to compare against the project.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExpectedDevice {
+  pub number: u32,
+  pub name: String,
+  pub kind: DeviceKind,
+}
+
+impl From<&Device> for ExpectedDevice {
+  fn from(device: &Device) -> Self {
+    Self {
+      number: device.number(),
+      name: device.name().to_owned(),
+      kind: device.kind(),
+    }
+  }
+}
+
+/// A device that has a different name on the gateway than expected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RenamedDevice {
+  pub number: u32,
+  pub expected: String,
+  pub actual: String,
+}
+
+/// A device that has a different kind on the gateway than expected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChangedKind {
+  pub number: u32,
+  pub expected: DeviceKind,
+  pub actual: DeviceKind,
+}
+
+/// The differences between a set of expected devices and the devices of
+/// the gateway, by device number. Every list is sorted by number.
+///
+/// Names are compared after the same normalization as
+/// [`EnetDevice::name`]. A device that was both renamed and changed kind is
+/// in both lists.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProjectDiff {
+  /// Devices on the gateway that were not expected.
+  pub added: Vec<ExpectedDevice>,
+
+  /// Expected devices that are not on the gateway.
+  pub removed: Vec<ExpectedDevice>,
+
+  pub renamed: Vec<RenamedDevice>,
+
+  pub kind_changed: Vec<ChangedKind>,
+}
+
+impl ProjectDiff {
+  /// Compare `expected` against `devices`. When several expected devices
+  /// have the same number, the last one is used.
+  pub fn new<'a>(
+    expected: impl IntoIterator<Item = &'a ExpectedDevice>,
+    devices: &[Device],
+  ) -> Self {
+    let mut expected: BTreeMap<u32, &ExpectedDevice> =
+      expected.into_iter().map(|e| (e.number, e)).collect();
+    let mut devices: Vec<&Device> = devices.iter().collect();
+    devices.sort_by_key(|d| d.number());
+
+    let mut diff = ProjectDiff::default();
+    for device in devices {
+      let number = device.number();
+      let expected = match expected.remove(&number) {
+        Some(expected) => expected,
+        None => {
+          diff.added.push(device.into());
+          continue;
+        }
+      };
+
+      if normalize_name(&expected.name) != device.name() {
+        diff.renamed.push(RenamedDevice {
+          number,
+          expected: expected.name.clone(),
+          actual: device.name().to_owned(),
+        });
+      }
+
+      if expected.kind != device.kind() {
+        diff.kind_changed.push(ChangedKind {
+          number,
+          expected: expected.kind,
+          actual: device.kind(),
+        });
+      }
+    }
+
+    diff.removed = expected.into_values().cloned().collect();
+    diff
+  }
+
+  /// Whether the devices are exactly as expected.
+  pub fn is_empty(&self) -> bool {
+    self.added.is_empty()
+      && self.removed.is_empty()
+      && self.renamed.is_empty()
+      && self.kind_changed.is_empty()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::dev::DeviceDesc;
+
+  fn device(number: u32, name: &str, kind: DeviceKind) -> Device {
+    Device::new(DeviceDesc::new(name.into(), number, kind), number).1
+  }
+
+  fn expected(number: u32, name: &str, kind: DeviceKind) -> ExpectedDevice {
+    ExpectedDevice {
+      number,
+      name: name.into(),
+      kind,
+    }
+  }
+
+  #[test]
+  fn project_diff_reports_drift() {
+    let devices = [
+      device(1, "Hallway", DeviceKind::Dimmer),
+      device(2, "Kitchen", DeviceKind::Binary),
+      device(3, "Porch", DeviceKind::Binary),
+      device(5, "Garage", DeviceKind::Blinds),
+    ];
+    let config = [
+      expected(1, " Hallway ", DeviceKind::Dimmer),
+      expected(2, "Kitchen lights", DeviceKind::Dimmer),
+      expected(3, "Porch", DeviceKind::Binary),
+      expected(4, "Attic", DeviceKind::Binary),
+    ];
+
+    let diff = ProjectDiff::new(&config, &devices);
+    assert_eq!(diff.added, vec![expected(5, "Garage", DeviceKind::Blinds)]);
+    assert_eq!(diff.removed, vec![expected(4, "Attic", DeviceKind::Binary)]);
+    assert_eq!(
+      diff.renamed,
+      vec![RenamedDevice {
+        number: 2,
+        expected: "Kitchen lights".into(),
+        actual: "Kitchen".into(),
+      }]
+    );
+    assert_eq!(
+      diff.kind_changed,
+      vec![ChangedKind {
+        number: 2,
+        expected: DeviceKind::Dimmer,
+        actual: DeviceKind::Binary,
+      }]
+    );
+    assert!(!diff.is_empty());
+
+    let config: Vec<_> = devices.iter().map(ExpectedDevice::from).collect();
+    assert!(ProjectDiff::new(&config, &devices).is_empty());
+  }
+}
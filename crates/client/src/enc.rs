@@ -1,4 +1,6 @@
-use enet_proto::{RequestEnvelope, Response};
+use crate::stats::DecoderCounters;
+use enet_proto::{EncoderOptions, RequestEnvelope, Response, ResponseKind};
+use std::sync::Arc;
 use thiserror::Error;
 use tokio::io;
 use tokio_util::codec::{Decoder, Encoder};
@@ -7,8 +9,8 @@ use tokio_util::codec::{Decoder, Encoder};
 pub(crate) struct EnetEncoder(enet_proto::EnetEncoder);
 
 impl EnetEncoder {
-  pub(crate) fn new() -> Self {
-    Self::default()
+  pub(crate) fn with_options(options: EncoderOptions) -> Self {
+    Self(enet_proto::EnetEncoder::with_options(options))
   }
 }
 
@@ -24,21 +26,57 @@ impl<'a> Encoder<&'a RequestEnvelope> for EnetEncoder {
   }
 }
 
-#[derive(Default)]
-pub(crate) struct EnetDecoder(enet_proto::EnetDecoder);
+#[cfg(feature = "raw")]
+impl<'a> Encoder<&'a serde_json::Value> for EnetEncoder {
+  type Error = EnetEncoderError;
+
+  fn encode(
+    &mut self,
+    item: &'a serde_json::Value,
+    dst: &mut bytes::BytesMut,
+  ) -> Result<(), Self::Error> {
+    self.0.encode_value(item, dst).map_err(Into::into)
+  }
+}
+
+pub(crate) struct EnetDecoder(enet_proto::EnetDecoder, Arc<DecoderCounters>);
 
 impl EnetDecoder {
-  pub(crate) fn new() -> Self {
-    Self::default()
+  pub(crate) fn new(counters: Arc<DecoderCounters>) -> Self {
+    Self(enet_proto::EnetDecoder::new(), counters)
+  }
+
+  pub(crate) fn last_error_cmd(&self) -> Option<&str> {
+    self.0.last_error_cmd()
   }
 }
 
+/// Frames that fail to parse are yielded as items rather than errors. The
+/// frame has already been split off the buffer at that point, so the next
+/// frame can still be decoded, while a decoder error would end the stream.
 impl Decoder for EnetDecoder {
-  type Item = Response;
+  type Item = Result<Response, enet_proto::EnetDecoderError>;
   type Error = EnetDecoderError;
 
   fn decode(&mut self, src: &mut bytes::BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-    self.0.decode(src).map_err(Into::into)
+    let before = self.0.stats();
+    let result = self.0.decode(src);
+    self.1.record(&before, &self.0.stats());
+    match &result {
+      Ok(Some(Response::Unknown(res))) => {
+        self
+          .1
+          .record_frame(ResponseKind::Unknown, self.0.last_frame_len());
+        self.1.record_unsupported(&res.kind, &res.protocol);
+      }
+      Ok(Some(response)) => self
+        .1
+        .record_frame(response.kind(), self.0.last_frame_len()),
+      Ok(None) => (),
+      Err(_) => self.1.record_error(),
+    }
+
+    Ok(result.transpose())
   }
 }
 
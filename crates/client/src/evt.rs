@@ -1,66 +1,394 @@
 use std::{
-  collections::BTreeMap,
+  collections::{BTreeMap, BTreeSet},
+  convert::TryFrom,
+  fmt,
   ops::ControlFlow,
   str::FromStr,
-  time::{Duration, SystemTime},
+  sync::Arc,
+  time::{Duration, Instant, SystemTime},
 };
 
+use crate::trace::{event, Level};
 use crate::{
-  conn::{Connection, RecvError},
-  dev::{DeviceBrightness, DeviceState, DeviceWriter},
+  conn::{Connection, Connector, RecvError},
+  dev::{parse_level, parse_setpoint, BlindsPosition, DeviceBrightness, DeviceState, DeviceWriter},
+  redact::NameRedaction,
+  retry::BackoffConfig,
+  stats::{DecoderCounters, EventCounters},
   ConnectError,
 };
 use backoff::{backoff::Backoff, ExponentialBackoff};
-use enet_proto::{ItemUpdateValue, ItemValueSignInReq, RequestEnvelope, Response};
-use tokio::{net::ToSocketAddrs, sync::mpsc};
-use tracing::{event, Level};
+use enet_proto::{
+  DecoderStats, ItemSetValue, ItemUpdateValue, ItemValueSignInReq, ItemValueSignOutReq,
+  RequestEnvelope, Response, SetValue,
+};
+use eventuals::{Eventual, EventualReader, EventualWriter};
+use tokio::sync::{broadcast, mpsc, oneshot};
+
+/// Number of gateway updates that can be buffered for each
+/// [`EventHandler::subscribe_gateway_updates`] receiver.
+const GATEWAY_UPDATE_CAPACITY: usize = 64;
+
+/// How long the gateway has to confirm the first sign-in by default.
+pub(crate) const DEFAULT_SIGN_IN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Settings for the event actor.
+#[derive(Debug, Clone)]
+pub(crate) struct EventOptions {
+  pub(crate) redaction: NameRedaction,
+  pub(crate) backoff: BackoffConfig,
+  pub(crate) deduplicate: bool,
+  pub(crate) hooks: LifecycleHooks,
+  pub(crate) reconcile: ReconcilePolicy,
+  pub(crate) sign_in_timeout: Duration,
+}
+
+impl Default for EventOptions {
+  fn default() -> Self {
+    Self {
+      redaction: NameRedaction::default(),
+      backoff: BackoffConfig::default(),
+      deduplicate: true,
+      hooks: LifecycleHooks::default(),
+      reconcile: ReconcilePolicy::default(),
+      sign_in_timeout: DEFAULT_SIGN_IN_TIMEOUT,
+    }
+  }
+}
+
+/// How updates from the gateway are applied after an optimistic update, see
+/// [`EnetClientBuilder::reconcile_policy`](crate::EnetClientBuilder::reconcile_policy).
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReconcilePolicy {
+  /// Every update from the gateway is applied as soon as it arrives.
+  GatewayWins,
+
+  /// For the given time after an optimistic update, updates from the gateway
+  /// with a different value are held back, as they are usually sent before
+  /// the gateway has applied the command. For dimmers the brightness has to
+  /// match as well. An update with the optimistic value ends the hold
+  /// early. Once the time is over, the latest update
+  /// that was held back is applied, so a rejected command is still shown
+  /// eventually.
+  HoldOptimistic(Duration),
+}
 
-struct EventActor<A: ToSocketAddrs + Clone> {
-  addr: A,
+impl Default for ReconcilePolicy {
+  fn default() -> Self {
+    ReconcilePolicy::GatewayWins
+  }
+}
+
+/// An optimistic value that gateway updates don't override yet.
+struct Hold {
+  value: SetValue,
+  until: Instant,
+  /// The latest update from the gateway that was held back.
+  held: Option<ItemUpdateValue>,
+}
+
+/// The holds of a [`ReconcilePolicy`], by device number.
+struct Holds {
+  policy: ReconcilePolicy,
+  holds: BTreeMap<u32, Hold>,
+}
+
+impl Holds {
+  fn new(policy: ReconcilePolicy) -> Self {
+    Self {
+      policy,
+      holds: BTreeMap::new(),
+    }
+  }
+
+  /// Start holding the optimistic `value` of a device after it was set at
+  /// `now`. Does nothing unless the policy holds optimistic values.
+  fn hold(&mut self, number: u32, value: SetValue, now: Instant) {
+    if let ReconcilePolicy::HoldOptimistic(window) = self.policy {
+      let hold = Hold {
+        value,
+        until: now + window,
+        held: None,
+      };
+      self.holds.insert(number, hold);
+    }
+  }
+
+  /// Hold `update` back if it would override an optimistic value too early.
+  /// Returns the update if it should be applied now.
+  fn reconcile(&mut self, update: ItemUpdateValue, now: Instant) -> Option<ItemUpdateValue> {
+    match self.holds.get_mut(&update.number) {
+      Some(hold) if hold.until > now && !agrees(hold.value, &update) => {
+        event!(target: "enet-client::evt", Level::DEBUG, update.number, %update.value, %update.state, "holding back update that differs from the optimistic value");
+        hold.held = Some(update);
+        None
+      }
+      // the hold is over, or the gateway agrees with it
+      Some(_) => {
+        self.holds.remove(&update.number);
+        Some(update)
+      }
+      None => Some(update),
+    }
+  }
+
+  /// When the next hold with an update to apply ends.
+  fn next_release(&self) -> Option<Instant> {
+    self
+      .holds
+      .values()
+      .filter(|hold| hold.held.is_some())
+      .map(|hold| hold.until)
+      .min()
+  }
+
+  /// End the holds that are over at `now`, and return the updates they held
+  /// back.
+  fn release(&mut self, now: Instant) -> Vec<ItemUpdateValue> {
+    let over = self
+      .holds
+      .iter()
+      .filter(|(_, hold)| hold.until <= now)
+      .map(|(number, _)| *number)
+      .collect::<Vec<_>>();
+
+    over
+      .into_iter()
+      .filter_map(|number| self.holds.remove(&number).and_then(|hold| hold.held))
+      .collect()
+  }
+}
+
+/// Whether the gateway `update` shows the optimistic `value`. For a dimmer
+/// this includes the brightness, so an update with the old brightness is
+/// still held back.
+fn agrees(value: SetValue, update: &ItemUpdateValue) -> bool {
+  let state = DeviceState::from_str(&*update.state).ok();
+  match value {
+    SetValue::On(_) => state == Some(DeviceState::On),
+    SetValue::Off(_) | SetValue::Dimm(0) => state == Some(DeviceState::Off),
+    SetValue::Dimm(level) => {
+      let brightness = parse_level::<DeviceBrightness>(&*update.value)
+        .ok()
+        .flatten();
+      state == Some(DeviceState::On) && brightness.map(DeviceBrightness::get) == Some(level)
+    }
+    SetValue::Blinds(level) => {
+      let position = parse_level::<BlindsPosition>(&*update.value).ok().flatten();
+      position.map(BlindsPosition::get) == Some(level)
+    }
+  }
+}
+
+pub(crate) type Hook = Arc<dyn Fn() + Send + Sync>;
+pub(crate) type ReconnectHook = Arc<dyn Fn(u32, Duration) + Send + Sync>;
+
+/// Callbacks for the transitions of the event connection, see
+/// [`EnetClientBuilder::on_connect`](crate::EnetClientBuilder::on_connect).
+#[derive(Clone, Default)]
+pub(crate) struct LifecycleHooks {
+  pub(crate) on_connect: Option<Hook>,
+  pub(crate) on_disconnect: Option<Hook>,
+  pub(crate) on_reconnect_attempt: Option<ReconnectHook>,
+}
+
+impl fmt::Debug for LifecycleHooks {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("LifecycleHooks")
+      .field("on_connect", &self.on_connect.is_some())
+      .field("on_disconnect", &self.on_disconnect.is_some())
+      .field("on_reconnect_attempt", &self.on_reconnect_attempt.is_some())
+      .finish()
+  }
+}
+
+/// State of the event connection, see
+/// [`EnetClient::subscribe_connection_status`](crate::EnetClient::subscribe_connection_status).
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionStatus {
+  /// Opening the first connection.
+  Connecting,
+
+  /// Signed in for device updates.
+  Connected,
+
+  /// The connection failed and will be reopened after `next_retry`.
+  /// `attempt` counts the reconnects since the last successful sign-in,
+  /// starting at 1.
+  Reconnecting { attempt: u32, next_retry: Duration },
+
+  /// The event actor has stopped, because the client was closed or it ran
+  /// out of retries.
+  Stopped,
+}
+
+struct EventActor {
+  connector: Arc<dyn Connector>,
+  config: EventOptions,
+  counters: Arc<DecoderCounters>,
+  events: Arc<EventCounters>,
   recv: mpsc::UnboundedReceiver<ActorMessage>,
   writers: BTreeMap<u32, DeviceWriter>,
+  /// Written every time the gateway confirms the sign-in.
+  signed_in: EventualWriter<()>,
+  /// When each device last got an update from the gateway.
+  last_updates: BTreeMap<u32, Instant>,
+  status: EventualWriter<ConnectionStatus>,
+  /// Reconnects since the last confirmed sign-in.
+  attempt: u32,
+  /// Whether a sign-in has been confirmed on the current connection.
+  connected: bool,
+  /// The number of every device the gateway sent an update for, once the
+  /// update has been written.
+  gateway_updates: broadcast::Sender<u32>,
+  holds: Holds,
 }
 
 enum ActorMessage {
-  SetStates(Vec<(u32, DeviceState)>),
+  /// Values that were set, to apply optimistically, and a sender to notify
+  /// once they all have been.
+  SetValues(Vec<ItemSetValue>, Option<oneshot::Sender<()>>),
+  SubscribedNumbers(oneshot::Sender<Vec<u32>>),
+  LastUpdate(u32, oneshot::Sender<Option<Instant>>),
+  /// Sign out and stop, then notify the sender if there is one.
+  Shutdown(Option<oneshot::Sender<()>>),
 }
 
-impl<A: ToSocketAddrs + Clone> EventActor<A> {
-  fn new(addr: A, recv: mpsc::UnboundedReceiver<ActorMessage>, writers: Vec<DeviceWriter>) -> Self {
-    let writers = writers.into_iter().map(|w| (w.index(), w)).collect();
+impl EventActor {
+  fn new(
+    connector: Arc<dyn Connector>,
+    config: EventOptions,
+    counters: Arc<DecoderCounters>,
+    events: Arc<EventCounters>,
+    recv: mpsc::UnboundedReceiver<ActorMessage>,
+    writers: Vec<DeviceWriter>,
+    signed_in: EventualWriter<()>,
+    status: EventualWriter<ConnectionStatus>,
+    gateway_updates: broadcast::Sender<u32>,
+  ) -> Self {
+    let writers = writers
+      .into_iter()
+      .map(|mut w| {
+        w.set_deduplicate(config.deduplicate);
+        (w.index(), w)
+      })
+      .collect();
+    let holds = Holds::new(config.reconcile);
 
     Self {
-      addr,
+      connector,
+      config,
+      counters,
+      events,
       recv,
       writers,
+      signed_in,
+      last_updates: BTreeMap::new(),
+      status,
+      attempt: 0,
+      connected: false,
+      gateway_updates,
+      holds,
     }
   }
 
-  async fn run(mut self) {
-    let mut backoff = ExponentialBackoff::default();
+  /// Run the actor, starting with the already opened `conn`.
+  async fn run(mut self, conn: Connection) {
+    let mut backoff = ExponentialBackoff::from(&self.config.backoff);
+    self.status.write(ConnectionStatus::Connecting);
 
+    let mut conn = Some(conn);
     loop {
-      let sleep_time = self.main(&mut backoff).await;
+      let sleep_time = self.main(&mut backoff, conn.take()).await;
+      self.disconnected();
       match sleep_time {
-        ControlFlow::Break(()) => return,
+        ControlFlow::Break(()) => break,
         ControlFlow::Continue(None) => {
+          self.status.write(ConnectionStatus::Stopped);
           event!(target: "enet-client::evt", Level::WARN, "ran out of retries - panicing");
           panic!("event connection ran out of retries.");
         }
-        ControlFlow::Continue(Some(duration)) => tokio::time::sleep(duration).await,
+        ControlFlow::Continue(Some(duration)) => {
+          self.events.record_reconnect();
+          self.attempt += 1;
+          self.status.write(ConnectionStatus::Reconnecting {
+            attempt: self.attempt,
+            next_retry: duration,
+          });
+          if let Some(hook) = &self.config.hooks.on_reconnect_attempt {
+            hook(self.attempt, duration);
+          }
+          if self.wait(duration).await.is_break() {
+            break;
+          }
+        }
+      }
+    }
+
+    self.status.write(ConnectionStatus::Stopped);
+  }
+
+  /// Called whenever the current connection is given up.
+  fn disconnected(&mut self) {
+    if std::mem::replace(&mut self.connected, false) {
+      if let Some(hook) = &self.config.hooks.on_disconnect {
+        hook();
+      }
+    }
+  }
+
+  /// Wait before reconnecting, while still answering actor messages.
+  async fn wait(&mut self, duration: Duration) -> ControlFlow<()> {
+    let sleep = tokio::time::sleep(duration);
+    tokio::pin!(sleep);
+
+    loop {
+      tokio::select! {
+        _ = &mut sleep => return ControlFlow::Continue(()),
+        msg = self.recv.recv() => match msg {
+          None => return ControlFlow::Break(()),
+          Some(ActorMessage::Shutdown(done)) => {
+            // not signed in while disconnected, so there is nothing to undo
+            if let Some(done) = done {
+              let _ = done.send(());
+            }
+            return ControlFlow::Break(());
+          }
+          Some(msg) => self.handle_msg(msg),
+        }
       }
     }
   }
 
-  async fn main(&mut self, backoff: &mut impl Backoff) -> ControlFlow<(), Option<Duration>> {
-    let mut conn = match Connection::new(self.addr.clone()).await {
+  /// Sign in on `conn`, or on a new connection when it is `None`, and apply
+  /// updates until the connection has to be given up.
+  async fn main(
+    &mut self,
+    backoff: &mut impl Backoff,
+    conn: Option<Connection>,
+  ) -> ControlFlow<(), Option<Duration>> {
+    let connect = match conn {
+      Some(conn) => Ok(conn),
+      None => self.connector.connect(self.counters.clone()).await,
+    };
+    let mut conn = match connect {
       Ok(conn) => conn,
+      Err(e) if !self.connector.can_reconnect() => {
+        event!(target: "enet-client::evt", Level::INFO, "event connection ended and cannot be reopened: {:?}", e);
+        return ControlFlow::Break(());
+      }
       Err(e) => {
         event!(target: "enet-client::evt", Level::WARN, "failed to open event connection to enet: {:?}", e);
         return ControlFlow::Continue(backoff.next_backoff());
       }
     };
 
+    // a new connection has no sign-ins, so every item has to be signed in
+    // again and there is no smaller diff to send. The set of items never
+    // changes while the actor runs, so the periodic sign-in below only renews
+    // the same set on the same connection.
     let subscribe_req = ItemValueSignInReq::new(self.writers.keys().copied().collect());
     let subscribe_msg = RequestEnvelope::new(subscribe_req.clone());
     if let Err(e) = conn.send(&subscribe_msg).await {
@@ -72,12 +400,25 @@ impl<A: ToSocketAddrs + Clone> EventActor<A> {
     loop {
       let duration = SystemTime::now().duration_since(then).unwrap();
       let wait_time = Duration::from_secs(60 * 5) - duration;
+      let release = self.holds.next_release();
 
       let msg = tokio::select! {
         v = self.recv.recv() => {
           match v {
             None =>
             return ControlFlow::Break(()),
+            Some(ActorMessage::Shutdown(done)) => {
+              let items = self.writers.keys().copied().collect();
+              let sign_out = RequestEnvelope::new(ItemValueSignOutReq::new(items));
+              if let Err(e) = conn.send(&sign_out).await {
+                event!(target: "enet-client::evt", Level::DEBUG, "failed to send sign out message to enet: {:?}", e);
+              }
+              self.disconnected();
+              if let Some(done) = done {
+                let _ = done.send(());
+              }
+              return ControlFlow::Break(());
+            }
             Some(v) => {
               self.handle_msg(v);
               continue;
@@ -94,6 +435,11 @@ impl<A: ToSocketAddrs + Clone> EventActor<A> {
           then = SystemTime::now();
           continue;
         }
+        _ = sleep_until(release) => {
+          let values = self.holds.release(Instant::now());
+          self.apply_gateway_values(values);
+          continue;
+        }
       };
 
       event!(target: "enet-client::evt", Level::DEBUG, "received message on evt connection");
@@ -103,6 +449,11 @@ impl<A: ToSocketAddrs + Clone> EventActor<A> {
           event!(target: "enet-client::evt", Level::ERROR, "connection closed");
           return ControlFlow::Continue(backoff.next_backoff());
         }
+        Result::Err(error) if error.is_bad_frame() => {
+          // the bad frame has been consumed, so keep reading the ones after it
+          event!(target: "enet-client::evt", Level::WARN, ?error, "skipping event that failed to decode");
+          continue;
+        }
         Result::Err(error) => {
           event!(target: "enet-client::evt", Level::WARN, ?error, "error when receiving event");
           return ControlFlow::Continue(backoff.next_backoff());
@@ -116,32 +467,75 @@ impl<A: ToSocketAddrs + Clone> EventActor<A> {
         }
         Response::ItemValueSignIn(_) => {
           backoff.reset();
+          self.attempt = 0;
+          self.status.write(ConnectionStatus::Connected);
+          // the sign-in is renewed periodically, which is not a new connection
+          if !std::mem::replace(&mut self.connected, true) {
+            if let Some(hook) = &self.config.hooks.on_connect {
+              hook();
+            }
+          }
+          self.signed_in.write(());
           continue;
         }
         _ => {
           event!(
             target: "enet-client::evt",
             Level::WARN,
-            msg.kind = ?msg.kind(),
+            msg.kind = msg.kind().as_str(),
             "received wrong message kind on event socket - starting connection anew");
           return ControlFlow::Continue(backoff.next_backoff());
         }
       };
 
+      for invalid in &update.invalid {
+        event!(
+          target: "enet-client::evt",
+          Level::WARN,
+          %invalid.value,
+          %invalid.error,
+          "skipping malformed value in update");
+      }
+
       self.update_values_from_enet(update.values);
     }
   }
 
   fn handle_msg(&mut self, msg: ActorMessage) {
     match msg {
-      ActorMessage::SetStates(values) => {
+      ActorMessage::SetValues(values, applied) => {
         event!(target: "enet-client::evt", Level::DEBUG, "received update for values via actor message");
-        self.update_device_states(values);
+        self.update_device_values(values);
+        if let Some(applied) = applied {
+          let _ = applied.send(());
+        }
+      }
+      ActorMessage::SubscribedNumbers(sender) => {
+        let _ = sender.send(self.writers.keys().copied().collect());
+      }
+      ActorMessage::LastUpdate(number, sender) => {
+        let _ = sender.send(self.last_updates.get(&number).copied());
       }
+      // needs the connection, so it is handled where the messages are
+      // received
+      ActorMessage::Shutdown(_) => (),
     }
   }
 
   fn update_values_from_enet(&mut self, values: Vec<ItemUpdateValue>) {
+    self.events.record_updates(values.len());
+    let now = Instant::now();
+    let values = values
+      .into_iter()
+      .filter_map(|value| self.holds.reconcile(value, now))
+      .collect();
+    self.apply_gateway_values(values);
+  }
+
+  fn apply_gateway_values(&mut self, values: Vec<ItemUpdateValue>) {
+    let redaction = self.config.redaction;
+    let now = Instant::now();
+    let mut updated = Vec::with_capacity(values.len());
     for value in values {
       let num = value.number;
       let writer = match self.writers.get_mut(&num) {
@@ -159,6 +553,8 @@ impl<A: ToSocketAddrs + Clone> EventActor<A> {
         Some(v) => v,
       };
 
+      self.last_updates.insert(num, now);
+      updated.push(num);
       event!(
         target: "enet-client::evt",
         Level::DEBUG,
@@ -167,11 +563,12 @@ impl<A: ToSocketAddrs + Clone> EventActor<A> {
         %value.state,
         %value.setpoint,
         device.kind = ?writer.kind(),
-        device.name = %writer.name(),
+        device.name = %redaction.name(writer.name()),
         "received update for value");
 
       match writer {
         DeviceWriter::Binary(w) => {
+          w.setpoint_writer.write(parse_setpoint(&*value.setpoint));
           if let Ok(state) = DeviceState::from_str(&*value.state) {
             w.state_writer.write(state);
           } else {
@@ -183,12 +580,41 @@ impl<A: ToSocketAddrs + Clone> EventActor<A> {
               %value.state,
               %value.setpoint,
               device.kind = ?w.kind(),
-              device.name = %w.name(),
+              device.name = %redaction.name(w.name()),
               "failed to convert '{}' to DeviceState",
               value.state);
           }
         }
         DeviceWriter::Dimmer(w) => {
+          w.raw_value_writer.write(value.value.to_string());
+          let setpoint = parse_level::<DeviceBrightness>(&*value.setpoint)
+            .ok()
+            .flatten();
+          let brightness = parse_level::<DeviceBrightness>(&*value.value);
+          w.set_target(brightness.ok().flatten(), setpoint);
+          match brightness {
+            Ok(Some(brightness)) => w.brightness_writer.write(brightness),
+            Ok(None) => {
+              // the gateway has no known brightness for the device, so the
+              // state can't be trusted either
+              w.state_writer.write(DeviceState::Unknown);
+              continue;
+            }
+            Err(_) => {
+              event!(
+                target: "enet-client::evt",
+                Level::WARN,
+                value.number,
+                %value.value,
+                %value.state,
+                %value.setpoint,
+                device.kind = ?w.kind(),
+                device.name = %redaction.name(w.name()),
+                "failed to convert '{}' to DeviceBrightness",
+                value.value);
+            }
+          }
+
           if let Ok(state) = DeviceState::from_str(&*value.state) {
             w.state_writer.write(state);
           } else {
@@ -200,16 +626,14 @@ impl<A: ToSocketAddrs + Clone> EventActor<A> {
               %value.state,
               %value.setpoint,
               device.kind = ?w.kind(),
-              device.name = %w.name(),
+              device.name = %redaction.name(w.name()),
               "failed to convert '{}' to DeviceState",
               value.state);
           }
-
-          if let Ok(brightness) = DeviceBrightness::from_str(&*value.value) {
-            w.brightness_writer.write(brightness);
-          } else if &*value.value == "-1" {
-            w.brightness_writer.write(DeviceBrightness::MIN);
-          } else {
+        }
+        DeviceWriter::Blinds(w) => match parse_level::<BlindsPosition>(&*value.value) {
+          Ok(position) => w.position_writer.write(position),
+          Err(_) => {
             event!(
               target: "enet-client::evt",
               Level::WARN,
@@ -218,53 +642,337 @@ impl<A: ToSocketAddrs + Clone> EventActor<A> {
               %value.state,
               %value.setpoint,
               device.kind = ?w.kind(),
-              device.name = %w.name(),
-              "failed to convert '{}' to DeviceBrightness",
+              device.name = %redaction.name(w.name()),
+              "failed to convert '{}' to BlindsPosition",
               value.value);
           }
-        }
+        },
       }
     }
+
+    for num in updated {
+      // there may be no receivers
+      let _ = self.gateway_updates.send(num);
+    }
   }
 
-  fn update_device_states(&mut self, values: Vec<(u32, DeviceState)>) {
-    for (num, state) in values {
+  fn update_device_values(&mut self, values: Vec<ItemSetValue>) {
+    let redaction = self.config.redaction;
+    let now = Instant::now();
+    for ItemSetValue { number: num, value } in values {
       let writer = match self.writers.get_mut(&num) {
         None => {
-          event!(target: "enet-client::evt", Level::WARN, value.number = num, value.state = %state, "received update for unknown number");
+          event!(target: "enet-client::evt", Level::WARN, value.number = num, value.set = ?value, "received update for unknown number");
           continue;
         }
         Some(v) => v,
       };
 
-      event!(target: "enet-client::evt", Level::DEBUG, value.number = num, value.state = %state, device.kind = ?writer.kind(), device.name = %writer.name(), "received manual update for value");
-      match writer {
-        DeviceWriter::Binary(w) => w.state_writer.write(state),
-        DeviceWriter::Dimmer(w) => w.state_writer.write(state),
+      event!(target: "enet-client::evt", Level::DEBUG, value.number = num, value.set = ?value, device.kind = ?writer.kind(), device.name = %redaction.name(writer.name()), "received manual update for value");
+      match (writer, value) {
+        (DeviceWriter::Blinds(w), SetValue::Blinds(level)) => {
+          if let Some(position) = BlindsPosition::new(level) {
+            w.position_writer.write(Some(position));
+          }
+          continue;
+        }
+        (DeviceWriter::Blinds(w), _) => {
+          event!(target: "enet-client::evt", Level::WARN, value.number = num, value.set = ?value, device.kind = ?w.kind(), device.name = %redaction.name(w.name()), "ignoring manual state update for blinds");
+          continue;
+        }
+        (w, SetValue::Blinds(_)) => {
+          event!(target: "enet-client::evt", Level::WARN, value.number = num, value.set = ?value, device.kind = ?w.kind(), device.name = %redaction.name(w.name()), "ignoring manual blinds position for a device that isn't blinds");
+          continue;
+        }
+        (DeviceWriter::Binary(w), value) => {
+          if let Ok(state) = DeviceState::try_from(value) {
+            w.state_writer.write(state);
+          }
+        }
+        (DeviceWriter::Dimmer(w), value) => {
+          // brightness first, so a dimmer that is turned on never shows the
+          // brightness it had before
+          if let SetValue::Dimm(level @ 1..=100) = value {
+            w.brightness_writer
+              .write(DeviceBrightness::new(level).unwrap());
+          }
+
+          if let Ok(state) = DeviceState::try_from(value) {
+            w.state_writer.write(state);
+          }
+        }
       }
+
+      self.holds.hold(num, value, now);
     }
   }
 }
 
+/// Sleep until `deadline`, or forever without one.
+async fn sleep_until(deadline: Option<Instant>) {
+  match deadline {
+    Some(deadline) => tokio::time::sleep_until(deadline.into()).await,
+    None => futures::future::pending().await,
+  }
+}
+
 pub(crate) struct EventHandler {
   sender: mpsc::UnboundedSender<ActorMessage>,
+  counters: Arc<DecoderCounters>,
+  events: Arc<EventCounters>,
+  signed_in: Eventual<()>,
+  status: Eventual<ConnectionStatus>,
+  gateway_updates: broadcast::Sender<u32>,
 }
 
 impl EventHandler {
+  /// Open the event connection and wait until the gateway confirms the
+  /// sign-in for device updates, for at most the sign-in timeout. Only later
+  /// drops of the connection are retried with backoff.
+  ///
+  /// Replayed sessions may not contain the confirmation, so for connections
+  /// that can't be reopened this returns once the connection is open.
   pub(crate) async fn new(
-    addr: impl ToSocketAddrs + Clone + Send + Sync + 'static,
+    connector: Arc<dyn Connector>,
+    config: EventOptions,
     writers: Vec<DeviceWriter>,
   ) -> Result<Self, ConnectError> {
+    let counters = Arc::<DecoderCounters>::default();
+    let conn = connector.connect(counters.clone()).await?;
+    let wait_for_sign_in = connector.can_reconnect();
+    let sign_in_timeout = config.sign_in_timeout;
+    let events = Arc::<EventCounters>::default();
     let (sender, receiver) = mpsc::unbounded_channel();
-    tokio::spawn(EventActor::new(addr, receiver, writers).run());
+    let (signed_in_writer, signed_in) = Eventual::new();
+    let (status_writer, status) = Eventual::new();
+    let gateway_updates = broadcast::channel(GATEWAY_UPDATE_CAPACITY).0;
+    let actor = EventActor::new(
+      connector,
+      config,
+      counters.clone(),
+      events.clone(),
+      receiver,
+      writers,
+      signed_in_writer,
+      status_writer,
+      gateway_updates.clone(),
+    );
+    tokio::spawn(actor.run(conn));
+
+    let handler = Self {
+      sender,
+      counters,
+      events,
+      signed_in,
+      status,
+      gateway_updates,
+    };
+
+    if wait_for_sign_in {
+      let signed_in = tokio::time::timeout(sign_in_timeout, handler.first_sign_in())
+        .await
+        .unwrap_or(false);
+      if !signed_in {
+        handler.shutdown();
+        return Err(ConnectError::SignIn);
+      }
+    }
+
+    Ok(handler)
+  }
+
+  /// Whether the gateway confirmed the sign-in before the first connection
+  /// was given up.
+  async fn first_sign_in(&self) -> bool {
+    let mut status = self.status.subscribe();
+    loop {
+      match status.next().await {
+        Ok(ConnectionStatus::Connected) => return true,
+        Ok(ConnectionStatus::Connecting) => continue,
+        // the reader only sees the latest status, so a sign-in may have been
+        // confirmed just before the connection failed
+        _ => return self.signed_in.value_immediate().is_some(),
+      }
+    }
+  }
+
+  /// The number of every device the gateway sends an update for, after the
+  /// device values have been written. Optimistic updates are not included.
+  pub(crate) fn subscribe_gateway_updates(&self) -> broadcast::Receiver<u32> {
+    self.gateway_updates.subscribe()
+  }
+
+  pub(crate) fn subscribe_status(&self) -> EventualReader<ConnectionStatus> {
+    self.status.subscribe()
+  }
+
+  pub(crate) fn status(&self) -> Option<ConnectionStatus> {
+    self.status.value_immediate()
+  }
+
+  pub(crate) fn decode_errors(&self) -> u64 {
+    self.counters.decode_errors()
+  }
+
+  pub(crate) fn reconnects(&self) -> u64 {
+    self.events.reconnects()
+  }
+
+  pub(crate) fn updates_received(&self) -> u64 {
+    self.events.updates_received()
+  }
 
-    Ok(Self { sender })
+  /// Wait for the first confirmed sign-in. Returns `false` if the actor
+  /// stopped before the gateway confirmed one.
+  pub(crate) async fn signed_in(&self) -> bool {
+    self.signed_in.subscribe().next().await.is_ok()
   }
 
-  pub(crate) fn update_values(&mut self, values: Vec<(u32, DeviceState)>) -> Result<(), ()> {
+  pub(crate) fn decoder_stats(&self) -> DecoderStats {
+    self.counters.snapshot()
+  }
+
+  pub(crate) fn unsupported_kinds(&self) -> BTreeSet<(String, String)> {
+    self.counters.unsupported_kinds()
+  }
+
+  /// The item numbers the actor signs in for, or an empty list if the actor
+  /// has stopped.
+  pub(crate) async fn subscribed_numbers(&self) -> Vec<u32> {
+    let (sender, receiver) = oneshot::channel();
+    if self
+      .sender
+      .send(ActorMessage::SubscribedNumbers(sender))
+      .is_err()
+    {
+      return Vec::new();
+    }
+
+    receiver.await.unwrap_or_default()
+  }
+
+  /// When the gateway last sent an update for the device, or `None` if it
+  /// hasn't yet or the actor has stopped.
+  pub(crate) async fn last_update(&self, number: u32) -> Option<Instant> {
+    let (sender, receiver) = oneshot::channel();
+    self
+      .sender
+      .send(ActorMessage::LastUpdate(number, sender))
+      .ok()?;
+
+    receiver.await.ok().flatten()
+  }
+
+  /// Sign out of the device updates and stop the actor, waiting until it has.
+  pub(crate) async fn close(&self) {
+    let (sender, receiver) = oneshot::channel();
+    if self
+      .sender
+      .send(ActorMessage::Shutdown(Some(sender)))
+      .is_ok()
+    {
+      let _ = receiver.await;
+    }
+  }
+
+  /// Like [`close`](Self::close), without waiting.
+  pub(crate) fn shutdown(&self) {
+    let _ = self.sender.send(ActorMessage::Shutdown(None));
+  }
+
+  pub(crate) fn update_values(&mut self, values: Vec<ItemSetValue>) -> Result<(), ()> {
     self
       .sender
-      .send(ActorMessage::SetStates(values))
+      .send(ActorMessage::SetValues(values, None))
       .map_err(|_| ())
   }
+
+  /// Like [`update_values`](Self::update_values), but waits until the actor
+  /// has written all of the values. The actor handles one message at a time,
+  /// so no gateway update is applied in between.
+  pub(crate) async fn apply_values(&mut self, values: Vec<ItemSetValue>) -> Result<(), ()> {
+    let (sender, receiver) = oneshot::channel();
+    self
+      .sender
+      .send(ActorMessage::SetValues(values, Some(sender)))
+      .map_err(|_| ())?;
+
+    receiver.await.map_err(|_| ())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const WINDOW: Duration = Duration::from_secs(1);
+
+  fn update(number: u32, value: &str, state: &str) -> ItemUpdateValue {
+    ItemUpdateValue {
+      number,
+      value: value.into(),
+      state: state.into(),
+      setpoint: "0".into(),
+    }
+  }
+
+  fn holding() -> Holds {
+    Holds::new(ReconcilePolicy::HoldOptimistic(WINDOW))
+  }
+
+  #[test]
+  fn updates_that_differ_are_held_back_within_the_window() {
+    let now = Instant::now();
+    let mut holds = holding();
+    holds.hold(1, SetValue::Dimm(40), now);
+
+    assert!(holds.reconcile(update(1, "80", "OFF"), now).is_none());
+    assert_eq!(holds.next_release(), Some(now + WINDOW));
+
+    // other devices are not held
+    assert!(holds.reconcile(update(2, "80", "OFF"), now).is_some());
+  }
+
+  #[test]
+  fn a_matching_update_ends_the_hold_early() {
+    let now = Instant::now();
+    let mut holds = holding();
+    holds.hold(1, SetValue::Dimm(40), now);
+
+    // on, but still at the old brightness
+    assert!(holds.reconcile(update(1, "80", "ON"), now).is_none());
+
+    let later = now + Duration::from_millis(10);
+    let applied = holds.reconcile(update(1, "40", "ON"), later).unwrap();
+    assert_eq!(applied.value, "40");
+    assert_eq!(holds.next_release(), None);
+    assert!(holds.reconcile(update(1, "80", "ON"), later).is_some());
+  }
+
+  #[test]
+  fn the_latest_held_update_is_released_when_the_window_ends() {
+    let now = Instant::now();
+    let mut holds = holding();
+    holds.hold(1, SetValue::turn_on(), now);
+
+    assert!(holds.reconcile(update(1, "10", "OFF"), now).is_none());
+    assert!(holds.reconcile(update(1, "20", "OFF"), now).is_none());
+    assert!(holds.release(now + WINDOW / 2).is_empty());
+
+    let released = holds.release(now + WINDOW);
+    assert_eq!(released.len(), 1);
+    assert_eq!(released[0].value, "20");
+    assert_eq!(holds.next_release(), None);
+  }
+
+  #[test]
+  fn gateway_wins_applies_every_update() {
+    let now = Instant::now();
+    let mut holds = Holds::new(ReconcilePolicy::GatewayWins);
+    holds.hold(1, SetValue::Dimm(40), now);
+
+    assert!(holds.reconcile(update(1, "80", "OFF"), now).is_some());
+    assert_eq!(holds.next_release(), None);
+    assert!(holds.release(now + WINDOW).is_empty());
+  }
 }
@@ -6,119 +6,1922 @@ macro_rules! bail {
   };
 }
 
+mod builder;
 pub mod cmd;
+mod coalesce;
 mod conn;
+mod detail;
 pub mod dev;
+mod diff;
 mod enc;
 mod evt;
+mod proj;
+mod redact;
+mod retry;
 mod room;
+mod settle;
+mod sink;
+mod stats;
+#[cfg(test)]
+mod testing;
+mod updates;
+mod validate;
+mod warning;
 
-use std::convert::TryFrom;
+use std::{
+  collections::{btree_map::Entry, BTreeMap, BTreeSet, HashMap},
+  convert::TryFrom,
+  sync::Arc,
+  time::{Duration, Instant},
+};
 
+pub use builder::EnetClientBuilder;
 pub use cmd::SetValuesCommandError;
-pub use conn::ConnectError;
-use dev::DeviceState;
-pub use dev::{BinaryDevice, Device, DimmerDevice, EnetDevice};
-pub use enet_proto::{ClickDuration, ItemSetValue, ItemValueRes, SetValue};
+pub use conn::{ConnectError, Keepalive, RecvError, SendError};
+pub use detail::{DeviceDetail, DimmerDetail};
+pub use dev::{
+  BinaryDevice, BlindsDevice, Device, DeviceId, DeviceMetadataOverride, DimmerDevice, EnetDevice,
+  GatewayId,
+};
+use dev::{BlindsPosition, DeviceBrightness, DeviceValue};
+pub use diff::{ChangedKind, ExpectedDevice, ProjectDiff, RenamedDevice};
+pub use enet_proto::{
+  ClickDuration, DecoderStats, EncoderOptions, ItemSetValue, ItemValueRes, ProjectItemKind,
+  ProtocolVersion, ResponseKind, SetValue, TimestampFormat,
+};
+pub use evt::{ConnectionStatus, ReconcilePolicy};
+pub use proj::ProjectItemInfo;
+pub use redact::NameRedaction;
+pub use retry::BackoffConfig;
+pub use room::Room;
+pub use settle::SettleReport;
+pub use sink::CommandSink;
+pub use stats::ClientStats;
+pub use updates::{DeviceEvent, DeviceSnapshot, DeviceTransaction, DeviceUpdate, InitialOrDelta};
+pub use validate::{ValidationError, ValidationResult};
+pub use warning::ClientWarning;
 
-use crate::{dev::DeviceDesc, room::RoomDesc};
-use cmd::CommandHandler;
+use crate::{
+  builder::ClientConfig,
+  coalesce::Coalescer,
+  conn::{Connector, ReplayConnector, TcpConnector},
+  dev::DeviceDesc,
+};
+use backoff::{backoff::Backoff, ExponentialBackoff};
+use bytes::Bytes;
+use cmd::{CommandError, CommandHandler, GetVersionCommandError};
+use enet_proto::{trace, Response};
+use eventuals::EventualReader;
 use evt::EventHandler;
+use futures::Stream;
 use thiserror::Error;
-use tokio::net::ToSocketAddrs;
-use tracing::{event, instrument, Level};
+use tokio::{net::ToSocketAddrs, sync::broadcast};
+use trace::{event, Level};
+
+/// Number of transactions kept for subscribers that fall behind.
+const TRANSACTION_CAPACITY: usize = 16;
+
+/// How long [`EnetClient::close`] waits for the answers to commands that
+/// have been sent.
+const CLOSE_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
 
 pub struct EnetClient {
-  #[allow(dead_code)]
   commands: CommandHandler,
-  #[allow(dead_code)]
-  events: EventHandler,
-  #[allow(dead_code)]
-  rooms: Vec<RoomDesc>,
+  events: Option<EventHandler>,
+  rooms: Vec<Room>,
+  items: Vec<ProjectItemInfo>,
   devices: Vec<Device>,
+  device_index: BTreeMap<u32, usize>,
+  device_names: BTreeSet<Arc<str>>,
+  /// Indexes into `devices`, sorted by name and then number.
+  devices_by_name: Vec<usize>,
+  warnings: Vec<ClientWarning>,
+  batch_size: usize,
+  project_id: String,
+  command_connector: Arc<dyn Connector>,
+  transactions: broadcast::Sender<DeviceTransaction>,
+  next_transaction: u64,
+  command_queue_capacity: usize,
+  channel_types: Vec<u32>,
+  coalescer: Option<Coalescer>,
+  read_only: bool,
+  closed: bool,
 }
 
 impl EnetClient {
-  #[instrument(level = "info", target = "enet-client", skip(addr), err)]
   pub async fn new<A>(addr: A) -> Result<Self, ClientConnectError>
   where
     A: ToSocketAddrs + Clone + Send + Sync + 'static,
   {
-    let mut commands = CommandHandler::new(addr.clone()).await?;
-    let version = commands.get_version().await?;
-    event!(target: "enet-client", Level::INFO, %version.firmware, %version.hardware, %version.enet, "connected to eNet Gateway");
+    Self::builder(addr).connect().await
+  }
+
+  pub fn builder<A>(addr: A) -> EnetClientBuilder<A>
+  where
+    A: ToSocketAddrs + Clone + Send + Sync + 'static,
+  {
+    EnetClientBuilder::new(addr)
+  }
+
+  /// Creates a client that replays a captured session instead of talking to a
+  /// gateway.
+  ///
+  /// `commands` and `events` are the bytes received on the command and event
+  /// sockets respectively, in the order they were received. Commands sent by
+  /// the client are discarded, and the client does not reconnect once either
+  /// stream has ended. Once the command stream has ended, or the command
+  /// connection has been closed after 15 seconds without commands, every
+  /// command fails with
+  /// [`CommandError::ConnectionClosed`](cmd::CommandError::ConnectionClosed).
+  pub async fn from_replay<C, E>(commands: C, events: E) -> Result<Self, ClientConnectError>
+  where
+    C: Stream<Item = Bytes> + Send + 'static,
+    E: Stream<Item = Bytes> + Send + 'static,
+  {
+    Self::connect_with(
+      Arc::new(ReplayConnector::new(commands)),
+      Arc::new(ReplayConnector::new(events)),
+      ClientConfig::default(),
+    )
+    .await
+  }
+
+  #[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(level = "info", target = "enet-client", skip(addr, config), err)
+  )]
+  async fn connect<A>(addr: A, config: ClientConfig) -> Result<Self, ClientConnectError>
+  where
+    A: ToSocketAddrs + Clone + Send + Sync + 'static,
+  {
+    let connector: Arc<dyn Connector> =
+      Arc::new(TcpConnector::new(addr, config.connection.clone()));
+
+    match config.connect_deadline {
+      Some(deadline) => tokio::time::timeout(deadline, Self::connect_retrying(connector, config))
+        .await
+        .unwrap_or(Err(ClientConnectError::Timeout { deadline })),
+      None => Self::connect_retrying(connector, config).await,
+    }
+  }
+
+  async fn connect_retrying(
+    connector: Arc<dyn Connector>,
+    config: ClientConfig,
+  ) -> Result<Self, ClientConnectError> {
+    let mut attempt = 0;
+    let mut backoff = ExponentialBackoff::from(&config.connect_backoff);
+    loop {
+      let error =
+        match Self::connect_with(connector.clone(), connector.clone(), config.clone()).await {
+          Ok(client) => return Ok(client),
+          // retrying won't make the gateway speak a different protocol
+          Err(e @ ClientConnectError::UnsupportedProtocol { .. }) => return Err(e),
+          Err(e) => e,
+        };
+
+      attempt += 1;
+      let delay = match backoff.next_backoff() {
+        Some(delay) if attempt <= config.connect_retries => delay,
+        _ => return Err(error),
+      };
+
+      event!(target: "enet-client", Level::WARN, attempt, ?delay, ?error, "failed to connect to gateway - retrying");
+      tokio::time::sleep(delay).await;
+    }
+  }
 
+  async fn connect_with(
+    command_connector: Arc<dyn Connector>,
+    events: Arc<dyn Connector>,
+    config: ClientConfig,
+  ) -> Result<Self, ClientConnectError> {
+    let mut commands =
+      Self::connect_commands(command_connector.clone(), config.command_queue_capacity).await?;
     let channel_types = commands.get_channel_info().await?;
     let project = commands.get_project().await?;
     let rooms = project
       .lists
       .into_iter()
       .filter(|l| l.visible)
-      .map(RoomDesc::from)
+      .map(Room::from)
       .collect::<Vec<_>>();
 
-    let (writers, devices) = project
+    let items = project
+      .items
+      .iter()
+      .map(ProjectItemInfo::from)
+      .collect::<Vec<_>>();
+
+    // the first device with a given number wins, so the outcome only depends
+    // on project order
+    let redaction = config.events.redaction;
+    let mut warnings = Vec::new();
+    let mut numbers = BTreeMap::<u32, Arc<str>>::new();
+    let mut descs = project
       .items
       .into_iter()
       .enumerate()
       .filter(|(idx, _)| channel_types.devices.get(*idx) == Some(&1))
       .filter_map(|(idx, item)| DeviceDesc::try_from(item).ok().map(|v| (idx, v)))
-      .map(|(idx, desc)| Device::new(desc, idx as u32))
+      .filter(|(_, desc)| match numbers.entry(desc.number) {
+        Entry::Vacant(entry) => {
+          entry.insert(desc.name.clone());
+          true
+        }
+        Entry::Occupied(entry) => {
+          event!(
+            target: "enet-client",
+            Level::WARN,
+            item.number = desc.number,
+            device.kept = %redaction.name(entry.get()),
+            device.dropped = %redaction.name(&desc.name),
+            "duplicate item number in project - keeping the first device");
+          warnings.push(ClientWarning::DuplicateItemNumber {
+            number: desc.number,
+            kept: entry.get().to_string(),
+            dropped: desc.name.to_string(),
+          });
+          false
+        }
+      })
+      .collect::<Vec<_>>();
+
+    if let Some(limit) = config.max_devices.filter(|limit| descs.len() > *limit) {
+      // keep the lowest numbers, in project order
+      let mut by_number = descs.iter().map(|(_, d)| d.number).collect::<Vec<_>>();
+      by_number.sort_unstable();
+      let highest_kept = by_number[limit.saturating_sub(1)];
+      let skipped = descs.len() - limit;
+      descs.retain(|(_, d)| limit > 0 && d.number <= highest_kept);
+      event!(
+        target: "enet-client",
+        Level::WARN,
+        devices.limit = limit,
+        devices.skipped = skipped,
+        "project has more devices than the configured maximum - skipping the highest numbers");
+      warnings.push(ClientWarning::TooManyDevices { limit, skipped });
+    }
+
+    let (writers, devices) = descs
+      .into_iter()
+      .map(|(idx, mut desc)| {
+        desc.gateway = config.gateway_id.clone();
+        if let Some(metadata) = config
+          .metadata_provider
+          .as_ref()
+          .and_then(|provider| (provider.0)(desc.number))
+        {
+          desc.apply_metadata(metadata);
+        }
+        Device::new(desc, idx as u32)
+      })
       .unzip();
 
-    let devices: Vec<_> = devices;
+    let devices: Vec<Device> = devices;
+    let device_index = devices
+      .iter()
+      .enumerate()
+      .map(|(idx, d)| (d.number(), idx))
+      .collect();
+    let device_names = devices.iter().map(|d| d.shared_name()).collect();
+    let mut devices_by_name: Vec<usize> = (0..devices.len()).collect();
+    devices_by_name.sort_by(|a, b| {
+      let (a, b) = (&devices[*a], &devices[*b]);
+      (a.name(), a.number()).cmp(&(b.name(), b.number()))
+    });
     event!(target: "enet-client", Level::INFO, rooms.len = %rooms.len(), devices.len = %devices.len(), "got project info");
 
-    let events = EventHandler::new(addr, writers).await?;
+    let coalescer = config
+      .coalesce_window
+      .map(|window| Coalescer::new(commands.clone(), window));
+
+    let events = if config.without_events {
+      // dropping the writers closes the device eventuals
+      drop(writers);
+      None
+    } else {
+      Some(EventHandler::new(events, config.events, writers).await?)
+    };
 
     Ok(Self {
       commands,
       events,
       rooms,
+      items,
       devices,
+      device_index,
+      device_names,
+      devices_by_name,
+      warnings,
+      batch_size: config.set_values_batch_size,
+      project_id: project.project_id,
+      command_connector,
+      transactions: broadcast::channel(TRANSACTION_CAPACITY).0,
+      next_transaction: 0,
+      command_queue_capacity: config.command_queue_capacity,
+      channel_types: channel_types.devices,
+      coalescer,
+      read_only: config.read_only,
+      closed: false,
     })
   }
 
+  /// Open a command connection and check that the gateway speaks a supported
+  /// protocol.
+  ///
+  /// The version request is the first frame on the connection, as the
+  /// gateway has no login request to send before it.
+  async fn connect_commands(
+    connector: Arc<dyn Connector>,
+    capacity: usize,
+  ) -> Result<CommandHandler, ClientConnectError> {
+    let mut commands = CommandHandler::new(connector, capacity).await?;
+    let version = commands.get_version().await.map_err(|e| match e {
+      // the gateway answered in a protocol version we can't parse
+      GetVersionCommandError::Command(CommandError::WrongResponse(Response::Unknown(res))) => {
+        match res.protocol.parse::<ProtocolVersion>() {
+          Ok(reported) if !reported.is_supported() => {
+            ClientConnectError::UnsupportedProtocol { reported }
+          }
+          _ => GetVersionCommandError::from(CommandError::WrongResponse(res.into())).into(),
+        }
+      }
+      e => e.into(),
+    })?;
+    event!(target: "enet-client", Level::INFO, %version.firmware, %version.hardware, %version.enet, "connected to eNet Gateway");
+
+    Ok(commands)
+  }
+
+  /// Replace the command connection with a new one.
+  ///
+  /// Once the command connection has failed, every command returns
+  /// [`CommandError::ConnectionClosed`](cmd::CommandError::ConnectionClosed).
+  /// This opens a new connection and validates it with a version request
+  /// before using it for further commands. The project is not reloaded, and
+  /// the command side of [`decoder_stats`](Self::decoder_stats) starts over.
+  pub async fn reconnect_commands(&mut self) -> Result<(), ClientConnectError> {
+    self.commands =
+      Self::connect_commands(self.command_connector.clone(), self.command_queue_capacity).await?;
+    if let Some(coalescer) = &self.coalescer {
+      coalescer.set_commands(self.commands.clone());
+    }
+
+    Ok(())
+  }
+
+  /// Shut the client down.
+  ///
+  /// The event connection signs out of the device updates before it is
+  /// closed, and this waits until it has. Device subscriptions end once the
+  /// client is closed.
+  ///
+  /// Commands that have already been sent, such as the coalesced values
+  /// still on their way, get up to 5 seconds to be answered by the gateway.
+  /// The ones that aren't then fail with
+  /// [`CommandError::ClientClosing`](cmd::CommandError::ClientClosing), as do
+  /// commands that were not sent yet. Nothing is sent once closing has
+  /// started.
+  pub async fn close(mut self) {
+    self.closed = true;
+    if let Some(coalescer) = &self.coalescer {
+      coalescer.flush().await;
+    }
+
+    if let Some(events) = &self.events {
+      events.close().await;
+    }
+
+    self.commands.close(CLOSE_DRAIN_TIMEOUT).await;
+  }
+
+  /// All items in the gateway project, in project order, including the ones
+  /// that are not exposed as [`Device`]s.
+  ///
+  /// `None` placeholders and scenes are always included, so editors can lay
+  /// out every slot of the project. Use
+  /// [`ProjectItemInfo::is_controllable`] to tell them apart.
+  pub fn project_items(&self) -> &[ProjectItemInfo] {
+    &self.items
+  }
+
+  /// The channel type codes reported by the gateway when connecting, one per
+  /// project item, in project order.
+  ///
+  /// The meaning of most codes is not known. Items with code `1` are exposed
+  /// as [`Device`]s, the others are skipped.
+  pub fn channel_info(&self) -> &[u32] {
+    &self.channel_types
+  }
+
+  /// Id of the project configured on the gateway.
+  ///
+  /// This stays the same when the gateway changes address, so it can be used
+  /// to tell gateways apart. The gateway does not report a serial number in
+  /// any of the responses the client receives.
+  pub fn project_id(&self) -> &str {
+    &*self.project_id
+  }
+
+  /// Problems with the gateway project that were recovered from while
+  /// connecting.
+  pub fn warnings(&self) -> &[ClientWarning] {
+    &self.warnings
+  }
+
+  /// The item numbers the event connection is signed in for, as reported by
+  /// the event actor. Empty when events are disabled.
+  pub async fn subscribed_numbers(&self) -> Vec<u32> {
+    match &self.events {
+      Some(events) => events.subscribed_numbers().await,
+      None => Vec::new(),
+    }
+  }
+
+  /// When the gateway last sent an update for the device.
+  ///
+  /// Only updates from the gateway count, not the optimistic updates applied
+  /// by [`set_values`](Self::set_values). The gateway repeats all values every
+  /// few minutes, so a device that has not been updated for much longer than
+  /// that can be treated as stale even when the connection is up. `None` if
+  /// there has been no update yet, or when events are disabled.
+  pub async fn last_update(&self, number: u32) -> Option<Instant> {
+    self.events.as_ref()?.last_update(number).await
+  }
+
+  /// Follow the state of the event connection, including the reconnect
+  /// attempt and the delay before the next one while it is down. `None` when
+  /// events are disabled.
+  pub fn subscribe_connection_status(&self) -> Option<EventualReader<ConnectionStatus>> {
+    self.events.as_ref().map(|events| events.subscribe_status())
+  }
+
+  /// Wait until the event connection has signed in for the device updates at
+  /// least once.
+  ///
+  /// Until then, the devices have no values yet. This returns immediately
+  /// once the first sign-in has been confirmed by the gateway, even if the
+  /// event connection has been reopened since.
+  pub async fn ready(&self) -> Result<(), ReadyError> {
+    let events = match &self.events {
+      Some(events) => events,
+      None => bail!(ReadyError::NoEvents),
+    };
+
+    if !events.signed_in().await {
+      bail!(ReadyError::Closed);
+    }
+
+    Ok(())
+  }
+
+  /// Frame statistics of the command and event connections combined.
+  pub fn decoder_stats(&self) -> DecoderStats {
+    let stats = self.commands.decoder_stats();
+    match &self.events {
+      Some(events) => stats.merge(&events.decoder_stats()),
+      None => stats,
+    }
+  }
+
+  /// A snapshot of the counters of the client, for feeding to a metrics
+  /// system.
+  pub fn stats(&self) -> ClientStats {
+    let (commands_sent, commands_failed) = self.commands.command_counts();
+    let mut stats = ClientStats {
+      reconnects: 0,
+      commands_sent,
+      commands_failed,
+      updates_received: 0,
+      decode_errors: self.commands.decode_errors(),
+      devices: self.devices.len(),
+      connection_status: None,
+    };
+
+    if let Some(events) = &self.events {
+      stats.reconnects = events.reconnects();
+      stats.updates_received = events.updates_received();
+      stats.decode_errors += events.decode_errors();
+      stats.connection_status = events.status();
+    }
+
+    stats
+  }
+
+  /// Size in bytes of the last response of the given kind received on the
+  /// command connection, including the frame delimiter.
+  ///
+  /// Mostly useful for [`ResponseKind::ProjectList`], which can be several
+  /// hundred KB for large installations. Frames that fail to parse are not
+  /// counted, and the sizes start over on
+  /// [`reconnect_commands`](Self::reconnect_commands).
+  pub fn last_response_size(&self, kind: ResponseKind) -> Option<usize> {
+    self.commands.last_frame_size(kind)
+  }
+
+  /// The `(CMD, PROTOCOL)` pairs of every response received that the client
+  /// doesn't model, sorted and without duplicates. These are the responses
+  /// logged as "received unknown response type", and point at gateway
+  /// firmware the client doesn't fully support yet.
+  ///
+  /// The command connection's pairs start over on
+  /// [`reconnect_commands`](Self::reconnect_commands).
+  pub fn unsupported_kinds(&self) -> Vec<(String, String)> {
+    let mut kinds = self.commands.unsupported_kinds();
+    if let Some(events) = &self.events {
+      kinds.extend(events.unsupported_kinds());
+    }
+
+    kinds.into_iter().collect()
+  }
+
+  /// Compare the devices of the gateway against the expected ones, for
+  /// instance from a configuration file. See [`ProjectDiff`].
+  pub fn project_diff<'a>(
+    &self,
+    expected: impl IntoIterator<Item = &'a ExpectedDevice>,
+  ) -> ProjectDiff {
+    ProjectDiff::new(expected, &self.devices)
+  }
+
+  /// The visible rooms in the gateway project.
+  pub fn rooms(&self) -> &[Room] {
+    &self.rooms
+  }
+
+  /// The devices in the room with the given number, in the order configured
+  /// on the gateway.
+  ///
+  /// Items in the room that are not controllable devices are skipped, and an
+  /// unknown or invisible room gives an empty list. A device may be in more
+  /// than one room.
+  pub fn devices_in_room(&self, room_number: u32) -> Vec<&Device> {
+    match self.rooms.iter().find(|r| r.number() == room_number) {
+      Some(room) => room.ordered_devices(self),
+      None => Vec::new(),
+    }
+  }
+
   pub fn devices(&self) -> &[Device] {
     &self.devices
   }
 
   pub fn device(&self, number: u32) -> Option<&Device> {
-    self.devices.iter().find(|d| d.number() == number)
+    self
+      .device_index
+      .get(&number)
+      .map(|idx| &self.devices[*idx])
   }
 
+  /// Everything known about a device, gathered from the project, its rooms
+  /// and the latest updates, or `None` if there is no device with `number`.
+  ///
+  /// This needs to ask the event actor for the last update time, so it is
+  /// async.
+  pub async fn device_detail(&self, number: u32) -> Option<DeviceDetail> {
+    let device = self.device(number)?;
+    let rooms = self
+      .rooms
+      .iter()
+      .filter(|room| room.items().contains(&number))
+      .cloned()
+      .collect();
+    let controllable = self
+      .items
+      .iter()
+      .find(|item| item.number() == number)
+      .map_or(false, |item| item.is_controllable());
+
+    let (setpoint, dimmer) = match device {
+      Device::Binary(binary) => (binary.setpoint.value_immediate().flatten(), None),
+      Device::Dimmer(dimmer) => {
+        let detail = DimmerDetail {
+          transitioning: dimmer.is_transitioning(),
+        };
+        (None, Some(detail))
+      }
+      Device::Blinds(_) => (None, None),
+    };
+
+    Some(DeviceDetail {
+      id: device.id(),
+      number,
+      name: device.shared_name(),
+      kind: device.kind(),
+      rooms,
+      value: device.value(),
+      last_update: self.last_update(number).await,
+      writable: !self.read_only && controllable,
+      setpoint,
+      dimmer,
+    })
+  }
+
+  /// Number of devices, the same as `devices().len()`.
+  #[inline]
+  pub fn device_count(&self) -> usize {
+    self.devices.len()
+  }
+
+  /// Whether there is a device with the given number.
+  #[inline]
+  pub fn contains_device(&self, number: u32) -> bool {
+    self.device_index.contains_key(&number)
+  }
+
+  /// The names of all devices, sorted and without duplicates.
+  ///
+  /// The names are sorted once when connecting, so this only allocates the
+  /// returned list.
+  pub fn device_names(&self) -> Vec<&str> {
+    self.device_names.iter().map(|name| &**name).collect()
+  }
+
+  /// All devices, sorted by name. Devices with the same name are sorted by
+  /// number.
+  ///
+  /// Like [`device_names`](Self::device_names), the order is computed once
+  /// when connecting.
+  pub fn devices_sorted_by_name(&self) -> Vec<&Device> {
+    self
+      .devices_by_name
+      .iter()
+      .map(|idx| &self.devices[*idx])
+      .collect()
+  }
+
+  /// Whether there is a device with the given name. This matches against the
+  /// normalized names, see [`EnetDevice::name`].
+  #[inline]
+  pub fn contains_name(&self, name: &str) -> bool {
+    self.device_names.contains(name)
+  }
+
+  /// Subscribe to the values of every device, keyed by device number.
+  ///
+  /// This creates a reader for each device up front, and each reader is
+  /// woken on every update of its device, so for large projects prefer
+  /// [`subscribe_each`](Self::subscribe_each) or [`updates`](Self::updates)
+  /// when only some of the readers are needed.
+  pub fn subscribe_all(&self) -> HashMap<u32, EventualReader<DeviceValue>> {
+    self.subscribe_each().collect()
+  }
+
+  /// Like [`subscribe_all`](Self::subscribe_all), but each device is only
+  /// subscribed to when the iterator gets to it.
+  pub fn subscribe_each(&self) -> impl Iterator<Item = (u32, EventualReader<DeviceValue>)> + '_ {
+    self.devices.iter().map(|d| (d.number(), d.subscribe()))
+  }
+
+  /// The latest known state of all devices.
+  pub fn snapshot(&self) -> Vec<DeviceSnapshot> {
+    self.devices.iter().map(DeviceSnapshot::from).collect()
+  }
+
+  /// A single stream of value updates for all devices.
+  ///
+  /// The first update for each device carries its current value, if it has one.
+  pub fn updates(&self) -> impl Stream<Item = DeviceUpdate> + Send + 'static {
+    updates::device_updates(&self.devices)
+  }
+
+  /// Wait until the value of a device matches `predicate`.
+  ///
+  /// The current value is checked first, so this returns immediately if the
+  /// device already has a matching value.
+  pub async fn wait_for_value(
+    &self,
+    number: u32,
+    predicate: impl Fn(&DeviceValue) -> bool,
+    timeout: Duration,
+  ) -> Result<DeviceValue, WaitError> {
+    let device = self
+      .device(number)
+      .ok_or(WaitError::UnknownDevice { number })?;
+
+    // subscribe first, so an update between the check and the wait isn't missed
+    let mut reader = device.subscribe();
+    let current = device.value();
+    if predicate(&current) {
+      return Ok(current);
+    }
+
+    let wait = async {
+      loop {
+        match reader.next().await {
+          Ok(value) if predicate(&value) => return Ok(value),
+          Ok(_) => continue,
+          Err(_) => return Err(WaitError::Closed),
+        }
+      }
+    };
+
+    match tokio::time::timeout(timeout, wait).await {
+      Ok(result) => result,
+      Err(_) => Err(WaitError::Timeout),
+    }
+  }
+
+  /// Wait until every device has a value other than
+  /// [`DeviceValue::Undefined`], or until `timeout` has passed.
+  ///
+  /// Devices have no value until the gateway first pushes one, so this is
+  /// a way to let the client settle after connecting. Without an event
+  /// connection no device can ever report, so this returns at once with
+  /// every device unreported.
+  pub async fn await_project_stable(&self, timeout: Duration) -> SettleReport {
+    let started = Instant::now();
+    let waits = self.devices.iter().map(|device| async move {
+      let number = device.number();
+      let reported = self
+        .wait_for_value(
+          number,
+          |value| !matches!(value, DeviceValue::Undefined),
+          timeout,
+        )
+        .await
+        .is_ok();
+      (number, reported)
+    });
+
+    let mut unreported = futures::future::join_all(waits)
+      .await
+      .into_iter()
+      .filter(|(_, reported)| !reported)
+      .map(|(number, _)| number)
+      .collect::<Vec<_>>();
+    unreported.sort_unstable();
+
+    SettleReport {
+      unreported,
+      elapsed: started.elapsed(),
+    }
+  }
+
+  /// Like [`updates`](Self::updates), but also reports when a device goes
+  /// offline (its value becomes undefined) and when it comes back.
+  pub fn device_events(&self) -> impl Stream<Item = DeviceEvent> + Send + 'static {
+    updates::device_events(&self.devices)
+  }
+
+  /// A stream that starts with the state of all devices, followed by the
+  /// updates for each device as they arrive.
+  ///
+  /// The subscriptions are set up before the snapshot is taken, so no update
+  /// is missed. Because of this, the first deltas may repeat values that are
+  /// already part of the snapshot.
+  pub fn devices_snapshot_stream(&self) -> impl Stream<Item = InitialOrDelta> + Send + 'static {
+    updates::snapshot_stream(&self.devices)
+  }
+
+  /// The optimistic updates of every
+  /// [`set_values_transaction`](Self::set_values_transaction) call, one item
+  /// per call.
+  ///
+  /// Subscribers that fall more than a few transactions behind skip the
+  /// oldest ones.
+  pub fn transactions(&self) -> impl Stream<Item = DeviceTransaction> + Send + 'static {
+    updates::transaction_stream(self.transactions.subscribe())
+  }
+
+  /// Set the value of a single device.
+  ///
+  /// With [`EnetClientBuilder::coalesce_set_value`], the value is queued, and
+  /// this returns without waiting for the gateway.
   pub async fn set_value(
     &mut self,
     number: u32,
     value: SetValue,
   ) -> Result<(), SetValuesCommandError> {
     let values = vec![ItemSetValue { number, value }];
+    if let Some(coalescer) = &self.coalescer {
+      self.check_writable()?;
+      if let Some(events) = &mut self.events {
+        let _ = events.update_values(values.clone());
+      }
+
+      coalescer.set(values.into_iter().next().unwrap());
+      return Ok(());
+    }
+
     self.set_values(values).await
   }
 
+  /// Set the value of a single device, and wait for the gateway to report
+  /// the new value.
+  ///
+  /// Unlike [`wait_for_value`](Self::wait_for_value) after
+  /// [`set_value`](Self::set_value), this only accepts an update pushed by
+  /// the gateway, not the optimistic update made when the command is
+  /// accepted, so it confirms that the device itself changed. Updates that
+  /// don't match, such as the intermediate values of a dimmer that is
+  /// ramping, are skipped. The value is never coalesced.
+  ///
+  /// Fails with [`WaitError::Timeout`] if no matching update arrives within
+  /// `timeout` of the command being accepted, and with [`WaitError::Closed`]
+  /// when the client has no event connection.
+  pub async fn set_value_confirmed(
+    &mut self,
+    number: u32,
+    value: SetValue,
+    timeout: Duration,
+  ) -> Result<DeviceValue, SetValueConfirmedError> {
+    if self.device(number).is_none() {
+      return Err(WaitError::UnknownDevice { number }.into());
+    }
+
+    // subscribe first, so an update that arrives with the answer isn't missed
+    let mut updates = match &self.events {
+      Some(events) => events.subscribe_gateway_updates(),
+      None => return Err(WaitError::Closed.into()),
+    };
+    self
+      .set_values(vec![ItemSetValue { number, value }])
+      .await?;
+
+    let device = self.device(number).unwrap();
+    let wait = async {
+      loop {
+        match updates.recv().await {
+          Ok(n) if n != number => continue,
+          // some updates were missed, so check the current value instead
+          Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {
+            let current = device.value();
+            if confirms(value, &current) {
+              return Ok(current);
+            }
+          }
+          Err(broadcast::error::RecvError::Closed) => return Err(WaitError::Closed),
+        }
+      }
+    };
+
+    match tokio::time::timeout(timeout, wait).await {
+      Ok(result) => Ok(result?),
+      Err(_) => Err(WaitError::Timeout.into()),
+    }
+  }
+
+  /// A [`Sink`](futures::Sink) of values to set, for forwarding a stream of
+  /// desired states into the client.
+  ///
+  /// Values that are sent to the sink one after the other without waiting,
+  /// like the ready items of a stream that is forwarded, are collected and
+  /// sent together by a single [`set_values`](Self::set_values) call once
+  /// the sink is flushed or a full batch (see
+  /// [`EnetClientBuilder::set_values_batch_size`]) is collected. The sink
+  /// fails with the first error of `set_values`.
+  pub fn command_sink(&mut self) -> CommandSink<'_> {
+    CommandSink::new(self)
+  }
+
+  /// Set the values of several devices.
+  ///
+  /// Large sets are split into batches (see
+  /// [`EnetClientBuilder::set_values_batch_size`]) that are sent one after the
+  /// other. Sending stops at the first batch that fails, in which case the
+  /// earlier batches have already been applied by the gateway.
   pub async fn set_values(
     &mut self,
     values: impl IntoIterator<Item = ItemSetValue>,
   ) -> Result<(), SetValuesCommandError> {
     let values: Vec<ItemSetValue> = values.into_iter().collect();
-    let new_states = values
-      .iter()
-      .map(|v| (v.number, DeviceState::from(v.value)))
-      .collect();
+    self.send_values(&values).await?;
+
+    if let Some(events) = &mut self.events {
+      let _ = events.update_values(values);
+    }
+
+    Ok(())
+  }
+
+  /// Like [`set_values`](Self::set_values), but fails instead of waiting
+  /// when the command queue is full (see
+  /// [`EnetClientBuilder::command_queue_capacity`]).
+  ///
+  /// The error is then a
+  /// [`CommandError::QueueFull`](cmd::CommandError::QueueFull), and nothing
+  /// of the failing batch was sent. This lets a UI drop a command that would
+  /// be stale by the time it is sent, rather than queueing it.
+  pub async fn try_set_values(
+    &mut self,
+    values: impl IntoIterator<Item = ItemSetValue>,
+  ) -> Result<(), SetValuesCommandError> {
+    let values: Vec<ItemSetValue> = values.into_iter().collect();
+    self.check_writable()?;
+    self.cancel_coalesced(&values);
+    for batch in values.chunks(self.batch_size) {
+      self.commands.try_set_values(batch.to_vec()).await?;
+    }
+
+    if let Some(events) = &mut self.events {
+      let _ = events.update_values(values);
+    }
+
+    Ok(())
+  }
+
+  /// Like [`set_values`](Self::set_values), but the optimistic updates for
+  /// all of the devices are published together.
+  ///
+  /// All of the new states are applied before any further update from the
+  /// gateway, so device streams never see a mix of old and new values from
+  /// the same call. Once they are applied, a single [`DeviceTransaction`]
+  /// with the returned id and the new values is published on
+  /// [`transactions`](Self::transactions), which lets a UI redraw the whole
+  /// group at once. Nothing is published when the client has no event
+  /// connection.
+  pub async fn set_values_transaction(
+    &mut self,
+    values: impl IntoIterator<Item = ItemSetValue>,
+  ) -> Result<u64, SetValuesCommandError> {
+    let values: Vec<ItemSetValue> = values.into_iter().collect();
+    let id = self.next_transaction;
+    self.next_transaction += 1;
+
+    self.send_values(&values).await?;
+
+    let applied = match &mut self.events {
+      Some(events) => events.apply_values(values.clone()).await.is_ok(),
+      None => false,
+    };
+
+    if applied {
+      let updates = values
+        .iter()
+        .filter_map(|v| {
+          self.device(v.number).map(|device| DeviceUpdate {
+            number: v.number,
+            value: optimistic_value(v.value, device.value()),
+          })
+        })
+        .collect();
+
+      // there may be no subscribers
+      let _ = self.transactions.send(DeviceTransaction { id, updates });
+    }
+
+    Ok(id)
+  }
+
+  /// Check values without sending them.
+  ///
+  /// Each value is checked for a device with its number that can be
+  /// controlled, and for a value that fits the kind of device and its range.
+  /// Returns one result per value, in the same order.
+  pub fn validate_values<'a>(
+    &self,
+    values: impl IntoIterator<Item = &'a ItemSetValue>,
+  ) -> Vec<ValidationResult> {
+    values
+      .into_iter()
+      .map(|value| {
+        let item = self.items.iter().find(|i| i.number() == value.number);
+        ValidationResult {
+          number: value.number,
+          result: validate::validate(value, self.device(value.number), item),
+        }
+      })
+      .collect()
+  }
 
-    self.commands.set_values(values).await?;
-    let _ = self.events.update_values(new_states);
+  fn check_writable(&self) -> Result<(), CommandError> {
+    if self.read_only {
+      bail!(cmd::ReadOnly);
+    }
 
     Ok(())
   }
+
+  async fn send_values(&mut self, values: &[ItemSetValue]) -> Result<(), SetValuesCommandError> {
+    self.check_writable()?;
+    self.cancel_coalesced(values);
+    for batch in values.chunks(self.batch_size) {
+      self.commands.set_values(batch.to_vec()).await?;
+    }
+
+    Ok(())
+  }
+
+  /// Drop coalesced values that are still waiting for the devices of
+  /// `values`, so they can't override the values sent directly.
+  fn cancel_coalesced(&self, values: &[ItemSetValue]) {
+    if let Some(coalescer) = &self.coalescer {
+      coalescer.cancel(values.iter().map(|v| v.number).collect());
+    }
+  }
+
+  /// Nudge a dimmer up or down by `delta` percent and return the new target
+  /// brightness.
+  ///
+  /// The step starts from the last known value of the dimmer, where off
+  /// counts as 0%, and is clamped to 0..=100%. The result is sent as an
+  /// absolute `Dimm` value, so repeated calls don't drift even when some
+  /// updates from the gateway are still outstanding.
+  pub async fn dim_relative(
+    &mut self,
+    number: u32,
+    delta: i8,
+  ) -> Result<DeviceBrightness, DimRelativeError> {
+    let device = self
+      .device(number)
+      .ok_or(DimRelativeError::UnknownDevice { number })?;
+    if !matches!(device, Device::Dimmer(_)) {
+      bail!(DimRelativeError::NotADimmer { number });
+    }
+
+    let current = match device.value() {
+      DeviceValue::Off => DeviceBrightness::MIN,
+      DeviceValue::On(brightness) => brightness,
+      _ => bail!(DimRelativeError::UnknownBrightness { number }),
+    };
+
+    let target = current.saturating_add_signed(delta);
+    self.set_value(number, SetValue::Dimm(target.get())).await?;
+
+    Ok(target)
+  }
+
+  /// Send a request the client has no type for, and return the response.
+  ///
+  /// `request` must be a JSON object with a `CMD` field, such as
+  /// `{"CMD":"BLOCK_LIST_REQ","LIST-RANGE":1}`. The `PROTOCOL` and
+  /// `TIMESTAMP` fields are added when missing. The response is the next one
+  /// the gateway sends that no earlier command is waiting for, whatever its
+  /// kind, so this is meant for trying out commands rather than for regular
+  /// use.
+  #[cfg(feature = "raw")]
+  pub async fn send_raw(
+    &mut self,
+    request: serde_json::Value,
+  ) -> Result<Response, cmd::CommandError> {
+    if self.read_only {
+      bail!(cmd::ReadOnly);
+    }
+
+    self.commands.send_raw(request).await
+  }
+
+  /// Turn off every binary and dimmer device.
+  ///
+  /// Blinds are left where they are, as "off" has no meaning for them.
+  pub async fn all_off(&mut self) -> Result<(), SetValuesCommandError> {
+    let values = off_values(self.devices.iter());
+    self.set_values(values).await
+  }
+
+  /// Turn off every binary and dimmer device in the given room.
+  ///
+  /// Blinds are left where they are. Nothing is sent if there is no visible
+  /// room with the given number.
+  pub async fn room_off(&mut self, room_number: u32) -> Result<(), SetValuesCommandError> {
+    let values = off_values(self.devices_in_room(room_number).into_iter());
+
+    self.set_values(values).await
+  }
 }
 
+/// Dropping the client without [`close`](EnetClient::close) still stops the
+/// event connection, but without waiting for it to sign out.
+impl Drop for EnetClient {
+  fn drop(&mut self) {
+    if self.closed {
+      return;
+    }
+
+    if let Some(events) = &self.events {
+      events.shutdown();
+      event!(target: "enet-client", Level::WARN, "client dropped without calling close() - signing out in the background");
+    }
+  }
+}
+
+/// The value of a device once `value` has been set, before the gateway
+/// confirms it. `current` is the value after the optimistic update.
+fn optimistic_value(value: SetValue, current: DeviceValue) -> DeviceValue {
+  match value {
+    SetValue::Off(_) | SetValue::Dimm(0) => DeviceValue::Off,
+    SetValue::Dimm(level) => DeviceBrightness::new(level).map_or(current, DeviceValue::On),
+    SetValue::Blinds(level) => BlindsPosition::new(level).map_or(current, DeviceValue::Blinds),
+    // a dimmer goes back to its last brightness, which only the device knows
+    SetValue::On(_) => current,
+  }
+}
+
+/// Whether a device with the value `current` has applied `value`.
+fn confirms(value: SetValue, current: &DeviceValue) -> bool {
+  match (value, current) {
+    (SetValue::On(_), DeviceValue::On(_)) => true,
+    (SetValue::Off(_) | SetValue::Dimm(0), DeviceValue::Off) => true,
+    (SetValue::Dimm(level), DeviceValue::On(brightness)) => brightness.get() == level,
+    (SetValue::Blinds(level), DeviceValue::Blinds(position)) => position.get() == level,
+    _ => false,
+  }
+}
+
+fn off_values<'a>(devices: impl Iterator<Item = &'a Device>) -> Vec<ItemSetValue> {
+  devices
+    .filter(|d| matches!(d, Device::Binary(_) | Device::Dimmer(_)))
+    .map(|d| ItemSetValue {
+      number: d.number(),
+      value: SetValue::Off(ClickDuration::Short),
+    })
+    .collect()
+}
+
+/// The variants that wrap another error return it from
+/// [`source`](std::error::Error::source), so the whole chain down to the
+/// I/O or decoding error can be reported.
 #[non_exhaustive]
 #[derive(Debug, Error)]
-#[error("Failed to connect to gateway.")]
 pub enum ClientConnectError {
+  #[error("Failed to connect to gateway.")]
   Connect(#[from] ConnectError),
+
+  #[error("Failed to connect to gateway.")]
   GetVersionCommand(#[from] cmd::GetVersionCommandError),
+
+  #[error("Failed to connect to gateway.")]
   GetChannelInfoCommand(#[from] cmd::GetChannelInfoCommandError),
+
+  #[error("Failed to connect to gateway.")]
   GetProjectCommand(#[from] cmd::GetProjectCommandError),
+
+  #[error("Failed to connect to gateway. It uses unsupported protocol version {reported}.")]
+  UnsupportedProtocol { reported: ProtocolVersion },
+
+  #[error("Failed to connect to gateway. The handshake took longer than {deadline:?}.")]
+  Timeout { deadline: Duration },
+}
+
+#[non_exhaustive]
+#[derive(Debug, Error)]
+pub enum DimRelativeError {
+  #[error("Failed to dim device. No device with number {number}.")]
+  UnknownDevice { number: u32 },
+
+  #[error("Failed to dim device. Device {number} is not a dimmer.")]
+  NotADimmer { number: u32 },
+
+  #[error("Failed to dim device. The brightness of device {number} is not known.")]
+  UnknownBrightness { number: u32 },
+
+  #[error("Failed to dim device.")]
+  Command(#[from] SetValuesCommandError),
+}
+
+#[non_exhaustive]
+#[derive(Debug, Error)]
+pub enum ReadyError {
+  #[error("Failed to wait for the event connection. The client was built without events.")]
+  NoEvents,
+
+  #[error("Failed to wait for the event connection. It stopped before signing in.")]
+  Closed,
+}
+
+#[non_exhaustive]
+#[derive(Debug, Error)]
+pub enum SetValueConfirmedError {
+  #[error("Failed to set value.")]
+  Set(#[from] SetValuesCommandError),
+
+  #[error("Failed to confirm value.")]
+  Wait(#[from] WaitError),
+}
+
+#[non_exhaustive]
+#[derive(Debug, Error)]
+pub enum WaitError {
+  #[error("Failed to wait for value. No device with number {number}.")]
+  UnknownDevice { number: u32 },
+
+  #[error("Failed to wait for value. Timed out.")]
+  Timeout,
+
+  #[error("Failed to wait for value. The device is no longer updated.")]
+  Closed,
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use futures::StreamExt;
+
+  #[tokio::test]
+  async fn set_values_sends_large_batches_in_chunks() {
+    let gateway = testing::dimmers(120).start().await;
+    let mut client = EnetClient::builder(gateway.addr())
+      .without_events()
+      .connect()
+      .await
+      .unwrap();
+
+    let values = (0..120).map(|number| ItemSetValue {
+      number,
+      value: SetValue::On(ClickDuration::Short),
+    });
+    client.set_values(values).await.unwrap();
+
+    let frames = gateway.requests("ITEM_VALUE_SET");
+    assert_eq!(frames.len(), 3);
+    assert_eq!(frames[2].matches(r#""NUMBER":"#).count(), 20);
+  }
+
+  #[tokio::test]
+  async fn coalesced_set_value_sends_the_latest_value_per_device() {
+    let gateway = testing::dimmers(2).start().await;
+    let mut client = EnetClient::builder(gateway.addr())
+      .without_events()
+      .coalesce_set_value(Duration::from_secs(60))
+      .connect()
+      .await
+      .unwrap();
+
+    for value in [10, 20, 30] {
+      client.set_value(0, SetValue::Dimm(value)).await.unwrap();
+    }
+    client.set_value(1, SetValue::Dimm(40)).await.unwrap();
+    assert!(gateway.requests("ITEM_VALUE_SET").is_empty());
+
+    // closing sends the pending values without waiting for the window
+    client.close().await;
+    let frames = gateway.requests("ITEM_VALUE_SET");
+    assert_eq!(frames.len(), 2);
+    assert!(frames.iter().any(|f| f.contains(r#""VALUE":30"#)));
+    assert!(frames.iter().any(|f| f.contains(r#""VALUE":40"#)));
+  }
+
+  #[tokio::test]
+  async fn direct_values_replace_pending_coalesced_ones() {
+    let gateway = testing::dimmers(1).start().await;
+    let mut client = EnetClient::builder(gateway.addr())
+      .without_events()
+      .coalesce_set_value(Duration::from_secs(60))
+      .connect()
+      .await
+      .unwrap();
+
+    client.set_value(0, SetValue::Dimm(30)).await.unwrap();
+    client
+      .set_values(vec![ItemSetValue {
+        number: 0,
+        value: SetValue::Dimm(60),
+      }])
+      .await
+      .unwrap();
+
+    // the coalesced value would be sent on close if it was still pending
+    client.close().await;
+    let frames = gateway.requests("ITEM_VALUE_SET");
+    assert_eq!(frames.len(), 1);
+    assert!(frames.last().unwrap().contains(r#""VALUE":60"#));
+  }
+
+  #[tokio::test]
+  async fn connect_fails_when_the_event_sign_in_fails() {
+    let gateway = testing::dimmers(1)
+      .reply_with(
+        "ITEM_VALUE_SIGN_IN_REQ",
+        r#"{"CMD":"ITEM_VALUE_RES","PROTOCOL":"0.03"}"#,
+      )
+      .start()
+      .await;
+
+    let result = EnetClient::builder(gateway.addr()).connect().await;
+    assert!(matches!(
+      result,
+      Err(ClientConnectError::Connect(ConnectError::SignIn))
+    ));
+  }
+
+  #[tokio::test]
+  async fn connect_fails_when_the_gateway_never_confirms_the_sign_in() {
+    let gateway = testing::dimmers(1)
+      .ignore("ITEM_VALUE_SIGN_IN_REQ")
+      .start()
+      .await;
+
+    let result = EnetClient::builder(gateway.addr())
+      .sign_in_timeout(Duration::from_millis(100))
+      .connect()
+      .await;
+    assert!(matches!(
+      result,
+      Err(ClientConnectError::Connect(ConnectError::SignIn))
+    ));
+  }
+
+  #[tokio::test]
+  async fn connect_errors_chain_their_sources() {
+    use std::error::Error as _;
+
+    let gateway = testing::dimmers(1)
+      .reply_with("PROJECT_LIST_GET", r#"{"CMD":}"#)
+      .start()
+      .await;
+    let error = match EnetClient::builder(gateway.addr()).connect().await {
+      Err(error) => error,
+      Ok(_) => panic!("connected with a broken project"),
+    };
+
+    let mut chain = Vec::new();
+    let mut next: Option<&(dyn std::error::Error + 'static)> = Some(&error);
+    while let Some(error) = next {
+      chain.push(error);
+      next = error.source();
+    }
+
+    assert!(chain[0].is::<ClientConnectError>());
+    assert!(chain[1].is::<cmd::GetProjectCommandError>());
+    assert!(chain[2].is::<CommandError>());
+    assert!(chain[3].is::<RecvError>());
+    assert!(chain[4].is::<enc::EnetDecoderError>());
+    // followed by the JSON error itself
+    assert_eq!(chain.len(), 6);
+    assert_eq!(
+      chain[1].to_string(),
+      "Failed to send 'get_project' command."
+    );
+  }
+
+  #[tokio::test]
+  async fn await_project_stable_reports_silent_devices() {
+    let gateway = testing::dimmers(2)
+      .push_before(
+        "ITEM_VALUE_SIGN_IN_REQ",
+        r#"{"CMD":"ITEM_UPDATE_IND","PROTOCOL":"0.03","VALUES":[{"NUMBER":"0","VALUE":"100","STATE":"ON","SETPOINT":"255"}]}"#,
+      )
+      .start()
+      .await;
+    let client = EnetClient::builder(gateway.addr()).connect().await.unwrap();
+
+    let report = client
+      .await_project_stable(Duration::from_millis(100))
+      .await;
+    assert_eq!(report.unreported, vec![1]);
+    assert!(!report.is_stable());
+    assert!(report.elapsed >= Duration::from_millis(100));
+  }
+
+  #[tokio::test]
+  async fn await_project_stable_returns_at_once_without_events() {
+    let gateway = testing::dimmers(2).start().await;
+    let client = EnetClient::builder(gateway.addr())
+      .without_events()
+      .connect()
+      .await
+      .unwrap();
+
+    let report = client.await_project_stable(Duration::from_secs(10)).await;
+    assert_eq!(report.unreported, vec![0, 1]);
+    assert!(report.elapsed < Duration::from_secs(1));
+  }
+
+  #[tokio::test]
+  async fn dimmers_without_a_known_level_are_undefined() {
+    let gateway = testing::dimmers(2)
+      .push_before(
+        "ITEM_VALUE_SIGN_IN_REQ",
+        r#"{"CMD":"ITEM_UPDATE_IND","PROTOCOL":"0.03","VALUES":[{"NUMBER":"0","VALUE":"50","STATE":"ON","SETPOINT":"255"}]}"#,
+      )
+      .push_before(
+        "ITEM_VALUE_SIGN_IN_REQ",
+        r#"{"CMD":"ITEM_UPDATE_IND","PROTOCOL":"0.03","VALUES":[{"NUMBER":"0","VALUE":"-1","STATE":"ON","SETPOINT":"255"},{"NUMBER":"1","VALUE":"30","STATE":"ON","SETPOINT":"255"}]}"#,
+      )
+      .start()
+      .await;
+    let client = EnetClient::builder(gateway.addr()).connect().await.unwrap();
+
+    // the second update has been applied once the other dimmer is on
+    let brightness = DeviceBrightness::new(30).unwrap();
+    client
+      .wait_for_value(
+        1,
+        |v| *v == DeviceValue::On(brightness),
+        Duration::from_secs(1),
+      )
+      .await
+      .unwrap();
+    assert_eq!(client.device(0).unwrap().value(), DeviceValue::Undefined);
+  }
+
+  #[tokio::test]
+  async fn replays_answer_commands_until_the_command_stream_ends() {
+    fn frames(frames: &[&str]) -> Vec<Bytes> {
+      frames
+        .iter()
+        .map(|frame| Bytes::from(format!("{}\r\n\r\n", frame)))
+        .collect()
+    }
+
+    // the replayed responses are held back a little, so every command is
+    // waiting by the time its response arrives
+    let commands = futures::stream::iter(frames(&[
+      r#"{"CMD":"VERSION_RES","PROTOCOL":"0.03","FIRMWARE":"0.91","HARDWARE":"73354100","ENET":"45068"}"#,
+      r#"{"CMD":"GET_CHANNEL_INFO_ALL_RES","PROTOCOL":"0.03","DEVICES":[1]}"#,
+      r#"{"CMD":"PROJECT_LIST_RES","PROTOCOL":"0.03","PROJECT_ID":"1","ITEMS":[{"TYPE":"DIMMER","NUMBER":0,"NAME":"Dimmer 0"}],"LISTS":[]}"#,
+      r#"{"CMD":"ITEM_VALUE_RES","PROTOCOL":"0.03"}"#,
+    ]))
+    .then(|frame| async move {
+      tokio::time::sleep(Duration::from_millis(20)).await;
+      frame
+    });
+    let events = futures::stream::iter(frames(&[
+      r#"{"CMD":"ITEM_VALUE_SIGN_IN_RES","PROTOCOL":"0.03"}"#,
+      r#"{"CMD":"ITEM_UPDATE_IND","PROTOCOL":"0.03","VALUES":[{"NUMBER":"0","VALUE":"100","STATE":"ON","SETPOINT":"255"}]}"#,
+    ]))
+    .chain(futures::stream::pending());
+
+    let mut client = EnetClient::from_replay(commands, events).await.unwrap();
+    assert_eq!(client.device(0).unwrap().name(), "Dimmer 0");
+    let brightness = DeviceBrightness::new(100).unwrap();
+    client
+      .wait_for_value(
+        0,
+        |v| *v == DeviceValue::On(brightness),
+        Duration::from_secs(1),
+      )
+      .await
+      .unwrap();
+
+    client.set_value(0, SetValue::Dimm(50)).await.unwrap();
+
+    // the command stream has ended, and a replay can't reconnect
+    assert!(matches!(
+      client.set_value(0, SetValue::Dimm(60)).await,
+      Err(SetValuesCommandError::Command(
+        CommandError::ConnectionClosed(_)
+      ))
+    ));
+  }
+
+  #[tokio::test]
+  async fn set_values_moves_blinds_optimistically() {
+    let gateway = testing::dimmers(1).with_blinds().start().await;
+    let mut client = EnetClient::builder(gateway.addr()).connect().await.unwrap();
+    client.ready().await.unwrap();
+
+    client
+      .set_values(vec![ItemSetValue {
+        number: 1,
+        value: SetValue::Blinds(30),
+      }])
+      .await
+      .unwrap();
+
+    let position = BlindsPosition::new(30).unwrap();
+    let value = client
+      .wait_for_value(
+        1,
+        |v| *v == DeviceValue::Blinds(position),
+        Duration::from_secs(1),
+      )
+      .await
+      .unwrap();
+    assert_eq!(value, DeviceValue::Blinds(position));
+
+    let frames = gateway.requests("ITEM_VALUE_SET");
+    assert_eq!(frames.len(), 1);
+    assert!(frames[0].contains(r#""STATE":"VALUE_BLINDS","VALUE":30"#));
+  }
+
+  #[tokio::test]
+  async fn validated_blinds_values_can_be_set() {
+    let gateway = testing::dimmers(1).with_blinds().start().await;
+    let mut client = EnetClient::builder(gateway.addr()).connect().await.unwrap();
+    client.ready().await.unwrap();
+
+    let values = vec![
+      ItemSetValue {
+        number: 0,
+        value: SetValue::Dimm(40),
+      },
+      ItemSetValue {
+        number: 1,
+        value: SetValue::Blinds(70),
+      },
+    ];
+    assert!(client
+      .validate_values(&values)
+      .iter()
+      .all(|v| v.result.is_ok()));
+
+    client.set_values(values).await.unwrap();
+    let frames = gateway.requests("ITEM_VALUE_SET");
+    assert_eq!(frames.len(), 1);
+    assert!(frames[0].contains(r#""STATE":"VALUE_BLINDS","VALUE":70"#));
+  }
+
+  #[tokio::test]
+  async fn unsupported_kinds_are_collected() {
+    let gateway = testing::dimmers(2)
+      .push_before(
+        "ITEM_VALUE_SET",
+        r#"{"CMD":"ITEM_VALUE_FOO_RES","PROTOCOL":"0.03"}"#,
+      )
+      .start()
+      .await;
+    let mut client = EnetClient::builder(gateway.addr()).connect().await.unwrap();
+    client.ready().await.unwrap();
+    assert!(client.unsupported_kinds().is_empty());
+
+    // the unknown responses are dropped, so the values are still answered
+    client.set_value(0, SetValue::Dimm(50)).await.unwrap();
+    client.set_value(1, SetValue::Dimm(50)).await.unwrap();
+    assert_eq!(
+      client.unsupported_kinds(),
+      vec![("ITEM_VALUE_FOO_RES".to_owned(), "0.03".to_owned())]
+    );
+  }
+
+  #[tokio::test]
+  async fn stats_count_commands_and_decode_errors() {
+    let gateway = testing::dimmers(2)
+      .reply_with("ITEM_VALUE_SET", r#"{"CMD":}"#)
+      .start()
+      .await;
+    let mut client = EnetClient::builder(gateway.addr()).connect().await.unwrap();
+    client.ready().await.unwrap();
+    assert!(client.set_value(0, SetValue::Dimm(50)).await.is_err());
+
+    let stats = client.stats();
+    // version, channel info, project and the failed set
+    assert_eq!(stats.commands_sent, 4);
+    assert_eq!(stats.commands_failed, 1);
+    assert_eq!(stats.decode_errors, 1);
+    assert_eq!(stats.devices, 2);
+    assert_eq!(stats.reconnects, 0);
+    assert_eq!(stats.connection_status, Some(ConnectionStatus::Connected));
+  }
+
+  #[tokio::test]
+  async fn subscribe_all_is_keyed_by_number() {
+    let gateway = testing::dimmers(3).start().await;
+    let client = EnetClient::builder(gateway.addr()).connect().await.unwrap();
+    client.ready().await.unwrap();
+
+    let mut readers = client.subscribe_all();
+    let mut numbers: Vec<_> = readers.keys().copied().collect();
+    numbers.sort_unstable();
+    assert_eq!(numbers, vec![0, 1, 2]);
+
+    client.set_value(1, SetValue::Dimm(40)).await.unwrap();
+    let reader = readers.get_mut(&1).unwrap();
+    let value = tokio::time::timeout(Duration::from_secs(5), async {
+      loop {
+        match reader.next().await.unwrap() {
+          DeviceValue::On(b) if b.get() == 40 => return,
+          _ => continue,
+        }
+      }
+    });
+    value.await.unwrap();
+  }
+
+  #[tokio::test]
+  async fn read_only_clients_send_no_values() {
+    let gateway = testing::dimmers(2).start().await;
+    let mut client = EnetClient::builder(gateway.addr())
+      .read_only()
+      .connect()
+      .await
+      .unwrap();
+    client.ready().await.unwrap();
+
+    let error = client.set_value(0, SetValue::Dimm(50)).await.unwrap_err();
+    assert!(matches!(
+      error,
+      SetValuesCommandError::Command(CommandError::ReadOnly(_))
+    ));
+    assert!(client.all_off().await.is_err());
+    assert!(client.try_set_values(Vec::new()).await.is_err());
+    assert!(gateway.requests("ITEM_VALUE_SET").is_empty());
+  }
+
+  #[tokio::test]
+  async fn connect_deadline_bounds_the_handshake() {
+    // a gateway that accepts the connection but never answers
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+      let mut sockets = Vec::new();
+      while let Ok((socket, _)) = listener.accept().await {
+        sockets.push(socket);
+      }
+    });
+
+    let result = EnetClient::builder(addr)
+      .connect_deadline(Duration::from_millis(100))
+      .connect()
+      .await;
+    assert!(matches!(
+      result,
+      Err(ClientConnectError::Timeout { deadline }) if deadline == Duration::from_millis(100)
+    ));
+  }
+
+  #[tokio::test]
+  async fn connection_status_follows_the_sign_in() {
+    let gateway = testing::dimmers(1).start().await;
+    let client = EnetClient::builder(gateway.addr()).connect().await.unwrap();
+    let mut status = client.subscribe_connection_status().unwrap();
+
+    let connected = async {
+      while let Ok(status) = status.next().await {
+        if status == ConnectionStatus::Connected {
+          return;
+        }
+      }
+    };
+    tokio::time::timeout(Duration::from_secs(5), connected)
+      .await
+      .unwrap();
+
+    client.close().await;
+    assert_eq!(status.next().await, Ok(ConnectionStatus::Stopped));
+  }
+
+  #[tokio::test]
+  async fn set_value_confirmed_waits_for_the_gateway() {
+    let gateway = testing::dimmers(2).start().await;
+    let mut client = EnetClient::builder(gateway.addr()).connect().await.unwrap();
+    client.ready().await.unwrap();
+
+    // the fake gateway accepts the command but never reports the new value
+    let result = client
+      .set_value_confirmed(1, SetValue::Dimm(40), Duration::from_millis(100))
+      .await;
+    assert!(matches!(
+      result,
+      Err(SetValueConfirmedError::Wait(WaitError::Timeout))
+    ));
+    assert_eq!(gateway.requests("ITEM_VALUE_SET").len(), 1);
+
+    let result = client
+      .set_value_confirmed(7, SetValue::Dimm(40), Duration::from_millis(100))
+      .await;
+    assert!(matches!(
+      result,
+      Err(SetValueConfirmedError::Wait(WaitError::UnknownDevice {
+        number: 7
+      }))
+    ));
+  }
+
+  #[test]
+  fn confirmed_values_must_match() {
+    let on = DeviceValue::On(DeviceBrightness::new(40).unwrap());
+    assert!(confirms(SetValue::Dimm(40), &on));
+    assert!(!confirms(SetValue::Dimm(80), &on));
+    assert!(confirms(SetValue::On(ClickDuration::Short), &on));
+    assert!(confirms(SetValue::Dimm(0), &DeviceValue::Off));
+    assert!(!confirms(SetValue::Off(ClickDuration::Short), &on));
+  }
+
+  #[tokio::test]
+  async fn command_sink_batches_ready_values() {
+    let gateway = testing::dimmers(4).start().await;
+    let mut client = EnetClient::builder(gateway.addr())
+      .without_events()
+      .connect()
+      .await
+      .unwrap();
+
+    let values = (0..3).map(|number| {
+      Ok::<_, SetValuesCommandError>(ItemSetValue {
+        number,
+        value: SetValue::Dimm(50),
+      })
+    });
+    futures::stream::iter(values)
+      .forward(client.command_sink())
+      .await
+      .unwrap();
+
+    let requests = gateway.requests("ITEM_VALUE_SET");
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].matches(r#""NUMBER":"#).count(), 3);
+  }
+
+  #[tokio::test]
+  async fn device_detail_gathers_what_is_known() {
+    use crate::dev::DeviceKind;
+
+    let gateway = testing::dimmers(2).start().await;
+    let client = EnetClient::builder(gateway.addr())
+      .read_only()
+      .without_events()
+      .connect()
+      .await
+      .unwrap();
+
+    let detail = client.device_detail(1).await.unwrap();
+    assert_eq!(detail.number, 1);
+    assert_eq!(&*detail.name, "Dimmer 1");
+    assert_eq!(detail.kind, DeviceKind::Dimmer);
+    assert!(detail.rooms.is_empty());
+    assert_eq!(detail.last_update, None);
+    assert!(!detail.writable);
+    assert_eq!(detail.dimmer.map(|d| d.transitioning), Some(false));
+
+    assert!(client.device_detail(5).await.is_none());
+  }
+
+  #[tokio::test]
+  async fn metadata_provider_overrides_names() {
+    let gateway = testing::dimmers(2).start().await;
+    let client = EnetClient::builder(gateway.addr())
+      .metadata_provider(|number| match number {
+        1 => Some(DeviceMetadataOverride {
+          name: Some("Reading lamp".into()),
+          tags: vec!["icon:lamp".into()],
+        }),
+        _ => None,
+      })
+      .without_events()
+      .connect()
+      .await
+      .unwrap();
+
+    let device = client.device(1).unwrap();
+    assert_eq!(device.name(), "Reading lamp");
+    assert_eq!(device.raw_name(), "Dimmer 1");
+    assert_eq!(device.tags(), &["icon:lamp".to_owned()]);
+    assert!(client.contains_name("Reading lamp"));
+    assert!(!client.contains_name("Dimmer 1"));
+
+    let other = client.device(0).unwrap();
+    assert_eq!(other.name(), "Dimmer 0");
+    assert!(other.tags().is_empty());
+  }
+
+  #[tokio::test]
+  async fn max_devices_keeps_the_lowest_numbers() {
+    let gateway = testing::dimmers(5).start().await;
+    let client = EnetClient::builder(gateway.addr())
+      .max_devices(3)
+      .without_events()
+      .connect()
+      .await
+      .unwrap();
+
+    let numbers = client
+      .devices()
+      .iter()
+      .map(|d| d.number())
+      .collect::<Vec<_>>();
+    assert_eq!(numbers, vec![0, 1, 2]);
+    assert_eq!(
+      client.warnings(),
+      &[ClientWarning::TooManyDevices {
+        limit: 3,
+        skipped: 2,
+      }]
+    );
+  }
+
+  #[tokio::test]
+  async fn lifecycle_hooks_see_connect_and_disconnect() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let connects = Arc::new(AtomicUsize::new(0));
+    let disconnects = Arc::new(AtomicUsize::new(0));
+    let gateway = testing::dimmers(1).start().await;
+    let client = EnetClient::builder(gateway.addr())
+      .on_connect({
+        let connects = connects.clone();
+        move || {
+          connects.fetch_add(1, Ordering::SeqCst);
+        }
+      })
+      .on_disconnect({
+        let disconnects = disconnects.clone();
+        move || {
+          disconnects.fetch_add(1, Ordering::SeqCst);
+        }
+      })
+      .connect()
+      .await
+      .unwrap();
+
+    client.ready().await.unwrap();
+    assert_eq!(connects.load(Ordering::SeqCst), 1);
+    assert_eq!(disconnects.load(Ordering::SeqCst), 0);
+
+    client.close().await;
+    assert_eq!(connects.load(Ordering::SeqCst), 1);
+    assert_eq!(disconnects.load(Ordering::SeqCst), 1);
+  }
+
+  #[tokio::test]
+  async fn ready_resolves_after_the_first_sign_in() {
+    let gateway = testing::dimmers(2).start().await;
+    let client = EnetClient::builder(gateway.addr()).connect().await.unwrap();
+
+    tokio::time::timeout(Duration::from_secs(5), client.ready())
+      .await
+      .unwrap()
+      .unwrap();
+    assert_eq!(gateway.requests("ITEM_VALUE_SIGN_IN_REQ").len(), 1);
+    assert!(client.ready().await.is_ok());
+  }
+
+  #[tokio::test]
+  async fn close_signs_out_of_updates() {
+    let gateway = testing::dimmers(2).start().await;
+    let client = EnetClient::builder(gateway.addr()).connect().await.unwrap();
+    client.ready().await.unwrap();
+    client.close().await;
+
+    // the frame has been written, but the gateway may not have read it yet
+    for _ in 0..50 {
+      if !gateway.requests("ITEM_VALUE_SIGN_OUT_REQ").is_empty() {
+        return;
+      }
+      tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("client did not sign out");
+  }
+
+  #[tokio::test]
+  async fn presence_checks_use_the_device_indexes() {
+    let gateway = testing::dimmers(3).start().await;
+    let client = EnetClient::builder(gateway.addr())
+      .without_events()
+      .connect()
+      .await
+      .unwrap();
+
+    assert_eq!(client.device_count(), 3);
+    assert_eq!(client.channel_info(), &[1, 1, 1]);
+    assert!(client.contains_device(2));
+    assert!(!client.contains_device(3));
+    assert_eq!(client.device(1).map(|d| d.number()), Some(1));
+    assert!(client.contains_name("Dimmer 0"));
+    assert!(!client.contains_name("Dimmer 3"));
+    assert_eq!(
+      client.device_names(),
+      vec!["Dimmer 0", "Dimmer 1", "Dimmer 2"]
+    );
+  }
+
+  #[tokio::test]
+  async fn response_sizes_are_tracked_per_kind() {
+    let gateway = testing::dimmers(3).start().await;
+    let client = EnetClient::builder(gateway.addr())
+      .without_events()
+      .connect()
+      .await
+      .unwrap();
+
+    let version = client.last_response_size(ResponseKind::Version).unwrap();
+    let project = client
+      .last_response_size(ResponseKind::ProjectList)
+      .unwrap();
+    assert!(project > version);
+    assert_eq!(client.last_response_size(ResponseKind::ItemUpdate), None);
+  }
+
+  #[tokio::test]
+  async fn set_values_transaction_publishes_one_item_per_call() {
+    let gateway = testing::dimmers(3).start().await;
+    let mut client = EnetClient::builder(gateway.addr()).connect().await.unwrap();
+    let mut transactions = Box::pin(client.transactions());
+
+    let values = |value| {
+      (0..3).map(move |number| ItemSetValue {
+        number,
+        value: SetValue::Dimm(value),
+      })
+    };
+    let first = client.set_values_transaction(values(40)).await.unwrap();
+    let second = client.set_values_transaction(values(0)).await.unwrap();
+
+    let brightness = DeviceBrightness::new(40).unwrap();
+    let transaction = transactions.next().await.unwrap();
+    assert_eq!(transaction.id, first);
+    let numbers: Vec<_> = transaction.updates.iter().map(|u| u.number).collect();
+    assert_eq!(numbers, vec![0, 1, 2]);
+    assert!(transaction
+      .updates
+      .iter()
+      .all(|u| u.value == DeviceValue::On(brightness)));
+
+    let transaction = transactions.next().await.unwrap();
+    assert_eq!(transaction.id, second);
+    assert!(transaction
+      .updates
+      .iter()
+      .all(|u| u.value == DeviceValue::Off));
+
+    // the brightness is written as well, so the devices agree with the
+    // transaction
+    client.set_values_transaction(values(40)).await.unwrap();
+    assert_eq!(
+      client.device(1).unwrap().value(),
+      DeviceValue::On(brightness)
+    );
+  }
 }
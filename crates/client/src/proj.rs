@@ -0,0 +1,91 @@
+use enet_proto::{EnetItem, ProjectItem, ProjectItemKind};
+
+/// Metadata about an item in the gateway project.
+///
+/// Unlike [`Device`](crate::Device), this is available for every item the
+/// gateway reports, including the ones the client does not model (yet).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProjectItemInfo {
+  number: u32,
+  name: String,
+  kind: ProjectItemKind,
+  subscribable: bool,
+  controllable: bool,
+}
+
+impl ProjectItemInfo {
+  #[inline]
+  pub fn number(&self) -> u32 {
+    self.number
+  }
+
+  #[inline]
+  pub fn name(&self) -> &str {
+    &*self.name
+  }
+
+  #[inline]
+  pub fn kind(&self) -> ProjectItemKind {
+    self.kind
+  }
+
+  #[inline]
+  pub fn is_subscribable(&self) -> bool {
+    self.subscribable
+  }
+
+  /// Whether the item can be controlled through the client. Items such as
+  /// `None` placeholders and scenes are listed for naming and layout only.
+  #[inline]
+  pub fn is_controllable(&self) -> bool {
+    self.controllable
+  }
+}
+
+impl From<&ProjectItem> for ProjectItemInfo {
+  fn from(item: &ProjectItem) -> Self {
+    Self {
+      number: item.number(),
+      name: item.name().into(),
+      kind: item.kind(),
+      subscribable: item.is_subscribable(),
+      controllable: match item {
+        ProjectItem::Binaer(v) => v.programmable,
+        ProjectItem::Dimmer(_) | ProjectItem::Jalousie(_) => true,
+        ProjectItem::Scene(_) | ProjectItem::None(_) => false,
+      },
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use enet_proto::{ProjectDimmer, ProjectNone, ProjectScene};
+
+  #[test]
+  fn placeholder_items_are_listed_but_not_controllable() {
+    let none = ProjectItemInfo::from(&ProjectItem::None(ProjectNone {
+      number: 3,
+      name: "Spare".into(),
+    }));
+    assert_eq!(none.number(), 3);
+    assert_eq!(none.name(), "Spare");
+    assert_eq!(none.kind(), ProjectItemKind::None);
+    assert!(!none.is_controllable());
+
+    let scene = ProjectItemInfo::from(&ProjectItem::Scene(ProjectScene {
+      number: 7,
+      name: "Evening".into(),
+      dimmable: false,
+    }));
+    assert_eq!(scene.kind(), ProjectItemKind::Scene);
+    assert!(!scene.is_controllable());
+
+    let dimmer = ProjectItemInfo::from(&ProjectItem::Dimmer(ProjectDimmer {
+      number: 16,
+      name: "Kitchen".into(),
+    }));
+    assert!(dimmer.is_controllable());
+  }
+}
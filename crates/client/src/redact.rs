@@ -0,0 +1,58 @@
+use std::{
+  collections::hash_map::DefaultHasher,
+  fmt,
+  hash::{Hash, Hasher},
+};
+
+/// How device names are written to emitted tracing events.
+///
+/// Device numbers are always logged as-is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NameRedaction {
+  /// Log device names unchanged.
+  Plain,
+
+  /// Replace device names with a fixed placeholder.
+  Redacted,
+
+  /// Replace device names with a hash of the name, so events for the same
+  /// device can still be correlated.
+  Hashed,
+}
+
+impl NameRedaction {
+  #[inline]
+  pub(crate) fn name(self, name: &str) -> LoggedName<'_> {
+    LoggedName {
+      name,
+      redaction: self,
+    }
+  }
+}
+
+impl Default for NameRedaction {
+  #[inline]
+  fn default() -> Self {
+    Self::Plain
+  }
+}
+
+/// A device name as it should appear in logs.
+pub(crate) struct LoggedName<'a> {
+  name: &'a str,
+  redaction: NameRedaction,
+}
+
+impl fmt::Display for LoggedName<'_> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self.redaction {
+      NameRedaction::Plain => f.write_str(self.name),
+      NameRedaction::Redacted => f.write_str("<redacted>"),
+      NameRedaction::Hashed => {
+        let mut hasher = DefaultHasher::new();
+        self.name.hash(&mut hasher);
+        write!(f, "{:016x}", hasher.finish())
+      }
+    }
+  }
+}
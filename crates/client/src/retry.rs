@@ -0,0 +1,149 @@
+use backoff::{ExponentialBackoff, ExponentialBackoffBuilder};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Reconnect policy for the event connection.
+///
+/// The delay before each reconnect starts at `initial_interval` and is
+/// multiplied by `multiplier` after every failed attempt, up to
+/// `max_interval`. Once `max_elapsed_time` has passed without a successful
+/// connection the event actor gives up. Setting `max_elapsed_time` to `None`
+/// makes it retry forever.
+///
+/// Each delay is randomized by up to `randomization_factor` in either
+/// direction, so clients that lost their connection at the same time (for
+/// instance because the gateway rebooted) do not all reconnect at once. The
+/// default is `0.5`, `0.0` turns the jitter off, and values above `1.0` are
+/// treated as `1.0`.
+///
+/// Missing fields take their value from [`BackoffConfig::default`] when
+/// deserializing, so a partial config can be loaded from a file.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BackoffConfig {
+  pub initial_interval: Duration,
+  pub max_interval: Duration,
+  pub multiplier: f64,
+  pub max_elapsed_time: Option<Duration>,
+  pub randomization_factor: f64,
+}
+
+impl BackoffConfig {
+  /// The default policy, to adjust with the methods below.
+  #[inline]
+  pub const fn new() -> Self {
+    Self {
+      initial_interval: Duration::from_millis(backoff::default::INITIAL_INTERVAL_MILLIS),
+      max_interval: Duration::from_millis(backoff::default::MAX_INTERVAL_MILLIS),
+      multiplier: backoff::default::MULTIPLIER,
+      max_elapsed_time: Some(Duration::from_millis(
+        backoff::default::MAX_ELAPSED_TIME_MILLIS,
+      )),
+      randomization_factor: backoff::default::RANDOMIZATION_FACTOR,
+    }
+  }
+
+  /// Delay before the first reconnect.
+  #[inline]
+  pub const fn initial_interval(mut self, initial_interval: Duration) -> Self {
+    self.initial_interval = initial_interval;
+    self
+  }
+
+  /// Longest delay between two reconnects.
+  #[inline]
+  pub const fn max_interval(mut self, max_interval: Duration) -> Self {
+    self.max_interval = max_interval;
+    self
+  }
+
+  /// Factor the delay grows by after every failed attempt.
+  #[inline]
+  pub const fn multiplier(mut self, multiplier: f64) -> Self {
+    self.multiplier = multiplier;
+    self
+  }
+
+  /// Time after which the event actor gives up, `None` to retry forever.
+  #[inline]
+  pub const fn max_elapsed_time(mut self, max_elapsed_time: Option<Duration>) -> Self {
+    self.max_elapsed_time = max_elapsed_time;
+    self
+  }
+
+  /// How much each delay is randomized, in either direction.
+  #[inline]
+  pub const fn randomization_factor(mut self, randomization_factor: f64) -> Self {
+    self.randomization_factor = randomization_factor;
+    self
+  }
+}
+
+impl Default for BackoffConfig {
+  #[inline]
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl From<&BackoffConfig> for ExponentialBackoff {
+  fn from(config: &BackoffConfig) -> Self {
+    ExponentialBackoffBuilder::new()
+      .with_initial_interval(config.initial_interval)
+      .with_max_interval(config.max_interval)
+      .with_multiplier(config.multiplier)
+      .with_max_elapsed_time(config.max_elapsed_time)
+      .with_randomization_factor(config.randomization_factor.clamp(0.0, 1.0))
+      .build()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use backoff::backoff::Backoff;
+
+  #[test]
+  fn backoff_config_maps_to_intervals() {
+    let config = BackoffConfig::new()
+      .initial_interval(Duration::from_secs(1))
+      .max_interval(Duration::from_secs(4))
+      .multiplier(2.0)
+      .max_elapsed_time(None)
+      .randomization_factor(0.5);
+
+    let mut backoff = ExponentialBackoff::from(&config);
+    backoff.randomization_factor = 0.0;
+    let intervals: Vec<_> = (0..4)
+      .map(|_| backoff.next_backoff().unwrap().as_millis())
+      .collect();
+
+    assert_eq!(intervals, vec![1000, 2000, 4000, 4000]);
+    assert_eq!(backoff.max_elapsed_time, None);
+  }
+
+  #[test]
+  fn backoff_intervals_are_jittered() {
+    let config = BackoffConfig {
+      max_elapsed_time: None,
+      ..BackoffConfig::default()
+    };
+
+    let intervals = |config: &BackoffConfig| {
+      let mut backoff = ExponentialBackoff::from(config);
+      (0..8)
+        .map(|_| backoff.next_backoff().unwrap())
+        .collect::<Vec<_>>()
+    };
+
+    // two clients with the same config drift apart
+    assert_ne!(intervals(&config), intervals(&config));
+
+    let config = BackoffConfig {
+      randomization_factor: 0.0,
+      ..config
+    };
+    assert_eq!(intervals(&config), intervals(&config));
+  }
+}
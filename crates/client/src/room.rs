@@ -1,13 +1,44 @@
+use crate::{Device, EnetClient};
 use enet_proto::ProjectList;
 
-#[allow(dead_code)]
-pub(crate) struct RoomDesc {
-  pub number: u32,
-  pub name: String,
-  pub items: Vec<u32>,
+/// A room (list) from the gateway project.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Room {
+  number: u32,
+  name: String,
+  items: Vec<u32>,
 }
 
-impl From<ProjectList> for RoomDesc {
+impl Room {
+  #[inline]
+  pub fn number(&self) -> u32 {
+    self.number
+  }
+
+  #[inline]
+  pub fn name(&self) -> &str {
+    &*self.name
+  }
+
+  /// Item numbers in the room, in the order configured on the gateway.
+  #[inline]
+  pub fn items(&self) -> &[u32] {
+    &self.items
+  }
+
+  /// The devices in the room, in the order configured on the gateway.
+  ///
+  /// Items that are not modeled as a [`Device`] are skipped.
+  pub fn ordered_devices<'a>(&self, client: &'a EnetClient) -> Vec<&'a Device> {
+    self
+      .items
+      .iter()
+      .filter_map(|number| client.device(*number))
+      .collect()
+  }
+}
+
+impl From<ProjectList> for Room {
   fn from(v: ProjectList) -> Self {
     Self {
       number: v.number,
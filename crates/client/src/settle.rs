@@ -0,0 +1,19 @@
+use std::time::Duration;
+
+/// The outcome of [`EnetClient::await_project_stable`](crate::EnetClient::await_project_stable).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SettleReport {
+  /// The devices that were still undefined when the timeout fired, by
+  /// number.
+  pub unreported: Vec<u32>,
+
+  /// How long the wait took.
+  pub elapsed: Duration,
+}
+
+impl SettleReport {
+  /// Whether every device reported a value before the timeout.
+  pub fn is_stable(&self) -> bool {
+    self.unreported.is_empty()
+  }
+}
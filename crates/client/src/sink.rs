@@ -0,0 +1,105 @@
+use crate::{EnetClient, SetValuesCommandError};
+use enet_proto::ItemSetValue;
+use futures::{future::BoxFuture, FutureExt, Sink};
+use std::{
+  mem,
+  pin::Pin,
+  task::{Context, Poll},
+};
+
+type SendResult<'a> = (&'a mut EnetClient, Result<(), SetValuesCommandError>);
+
+enum State<'a> {
+  Idle(&'a mut EnetClient),
+  Sending(BoxFuture<'a, SendResult<'a>>),
+  /// Only while switching between the other states.
+  Switching,
+}
+
+/// A [`Sink`] of values to set, see [`EnetClient::command_sink`].
+#[must_use = "sinks do nothing unless polled"]
+pub struct CommandSink<'a> {
+  state: State<'a>,
+  buffer: Vec<ItemSetValue>,
+  batch_size: usize,
+}
+
+impl<'a> CommandSink<'a> {
+  pub(crate) fn new(client: &'a mut EnetClient) -> Self {
+    let batch_size = client.batch_size;
+    Self {
+      state: State::Idle(client),
+      buffer: Vec::new(),
+      batch_size,
+    }
+  }
+
+  /// Wait for the values that are being sent, if any.
+  fn poll_sending(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), SetValuesCommandError>> {
+    let (client, result) = match &mut self.state {
+      State::Sending(send) => match send.poll_unpin(cx) {
+        Poll::Ready(done) => done,
+        Poll::Pending => return Poll::Pending,
+      },
+      _ => return Poll::Ready(Ok(())),
+    };
+
+    self.state = State::Idle(client);
+    Poll::Ready(result)
+  }
+
+  /// Send the buffered values as a single `set_values` call.
+  fn start_sending(&mut self) {
+    let client = match mem::replace(&mut self.state, State::Switching) {
+      State::Idle(client) => client,
+      _ => unreachable!("values are only sent when no other values are in flight"),
+    };
+
+    let values = mem::take(&mut self.buffer);
+    self.state = State::Sending(
+      async move {
+        let result = client.set_values(values).await;
+        (client, result)
+      }
+      .boxed(),
+    );
+  }
+}
+
+impl<'a> Sink<ItemSetValue> for CommandSink<'a> {
+  type Error = SetValuesCommandError;
+
+  fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+    let this = self.get_mut();
+    if this.buffer.len() >= this.batch_size {
+      return Pin::new(this).poll_flush(cx);
+    }
+
+    this.poll_sending(cx)
+  }
+
+  fn start_send(self: Pin<&mut Self>, item: ItemSetValue) -> Result<(), Self::Error> {
+    self.get_mut().buffer.push(item);
+    Ok(())
+  }
+
+  fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+    let this = self.get_mut();
+    loop {
+      match this.poll_sending(cx) {
+        Poll::Ready(Ok(())) => (),
+        other => return other,
+      }
+
+      if this.buffer.is_empty() {
+        return Poll::Ready(Ok(()));
+      }
+
+      this.start_sending();
+    }
+  }
+
+  fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+    self.poll_flush(cx)
+  }
+}
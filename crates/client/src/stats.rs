@@ -0,0 +1,179 @@
+use crate::ConnectionStatus;
+use enet_proto::{DecoderStats, ResponseKind};
+use std::{
+  collections::BTreeSet,
+  sync::{
+    atomic::{AtomicU64, AtomicUsize, Ordering},
+    Mutex,
+  },
+};
+
+/// Decoder counters shared between all connections of a client.
+#[derive(Debug, Default)]
+pub(crate) struct DecoderCounters {
+  frames_decoded: AtomicU64,
+  bytes_consumed: AtomicU64,
+  largest_frame: AtomicUsize,
+  decode_errors: AtomicU64,
+  // size of the last frame of each response kind, there are only a handful
+  last_frames: Mutex<Vec<(ResponseKind, usize)>>,
+  /// The `(CMD, PROTOCOL)` of every response the client doesn't model.
+  unsupported: Mutex<BTreeSet<(String, String)>>,
+}
+
+impl DecoderCounters {
+  /// Record the difference between two snapshots of the same decoder.
+  pub(crate) fn record(&self, before: &DecoderStats, after: &DecoderStats) {
+    // empty frames consume bytes without being counted as frames
+    if after.bytes_consumed == before.bytes_consumed {
+      return;
+    }
+
+    self.frames_decoded.fetch_add(
+      after.frames_decoded - before.frames_decoded,
+      Ordering::Relaxed,
+    );
+    self.bytes_consumed.fetch_add(
+      after.bytes_consumed - before.bytes_consumed,
+      Ordering::Relaxed,
+    );
+    self
+      .largest_frame
+      .fetch_max(after.largest_frame, Ordering::Relaxed);
+  }
+
+  /// Record a frame that failed to parse.
+  pub(crate) fn record_error(&self) {
+    self.decode_errors.fetch_add(1, Ordering::Relaxed);
+  }
+
+  pub(crate) fn decode_errors(&self) -> u64 {
+    self.decode_errors.load(Ordering::Relaxed)
+  }
+
+  /// Record the size of a frame that parsed as a response of `kind`.
+  pub(crate) fn record_frame(&self, kind: ResponseKind, len: usize) {
+    let mut last_frames = self.last_frames.lock().unwrap();
+    match last_frames.iter_mut().find(|(k, _)| *k == kind) {
+      Some((_, last)) => *last = len,
+      None => last_frames.push((kind, len)),
+    }
+  }
+
+  pub(crate) fn last_frame_size(&self, kind: ResponseKind) -> Option<usize> {
+    let last_frames = self.last_frames.lock().unwrap();
+    last_frames
+      .iter()
+      .find(|(k, _)| *k == kind)
+      .map(|(_, len)| *len)
+  }
+
+  /// Record a response that was decoded as
+  /// [`Response::Unknown`](enet_proto::Response::Unknown).
+  pub(crate) fn record_unsupported(&self, kind: &str, protocol: &str) {
+    self
+      .unsupported
+      .lock()
+      .unwrap()
+      .insert((kind.to_owned(), protocol.to_owned()));
+  }
+
+  pub(crate) fn unsupported_kinds(&self) -> BTreeSet<(String, String)> {
+    self.unsupported.lock().unwrap().clone()
+  }
+
+  pub(crate) fn snapshot(&self) -> DecoderStats {
+    DecoderStats {
+      frames_decoded: self.frames_decoded.load(Ordering::Relaxed),
+      bytes_consumed: self.bytes_consumed.load(Ordering::Relaxed),
+      largest_frame: self.largest_frame.load(Ordering::Relaxed),
+    }
+  }
+}
+
+/// Counters of the commands sent on a command connection.
+#[derive(Debug, Default)]
+pub(crate) struct CommandCounters {
+  sent: AtomicU64,
+  failed: AtomicU64,
+}
+
+impl CommandCounters {
+  /// Record a command whose frame was written to the connection.
+  pub(crate) fn record_sent(&self) {
+    self.sent.fetch_add(1, Ordering::Relaxed);
+  }
+
+  /// Record a command that failed, whether it was sent or not.
+  pub(crate) fn record_failed(&self) {
+    self.failed.fetch_add(1, Ordering::Relaxed);
+  }
+
+  pub(crate) fn sent(&self) -> u64 {
+    self.sent.load(Ordering::Relaxed)
+  }
+
+  pub(crate) fn failed(&self) -> u64 {
+    self.failed.load(Ordering::Relaxed)
+  }
+}
+
+/// Counters of the event actor.
+#[derive(Debug, Default)]
+pub(crate) struct EventCounters {
+  reconnects: AtomicU64,
+  updates_received: AtomicU64,
+}
+
+impl EventCounters {
+  pub(crate) fn record_reconnect(&self) {
+    self.reconnects.fetch_add(1, Ordering::Relaxed);
+  }
+
+  pub(crate) fn record_updates(&self, count: usize) {
+    self
+      .updates_received
+      .fetch_add(count as u64, Ordering::Relaxed);
+  }
+
+  pub(crate) fn reconnects(&self) -> u64 {
+    self.reconnects.load(Ordering::Relaxed)
+  }
+
+  pub(crate) fn updates_received(&self) -> u64 {
+    self.updates_received.load(Ordering::Relaxed)
+  }
+}
+
+/// A snapshot of the counters of a client, see
+/// [`EnetClient::stats`](crate::EnetClient::stats).
+///
+/// The counters only ever go up, except for the command counters, which
+/// start over with
+/// [`reconnect_commands`](crate::EnetClient::reconnect_commands).
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientStats {
+  /// Times the event connection was reopened after failing.
+  pub reconnects: u64,
+
+  /// Commands written to the command connection, including the ones that
+  /// failed afterwards.
+  pub commands_sent: u64,
+
+  /// Commands that failed, for any reason. This includes commands that were
+  /// never sent, because the queue was full or the client was closed.
+  pub commands_failed: u64,
+
+  /// Device values received from the gateway on the event connection.
+  pub updates_received: u64,
+
+  /// Frames on either connection that failed to parse.
+  pub decode_errors: u64,
+
+  /// Number of devices.
+  pub devices: usize,
+
+  /// State of the event connection, `None` when events are disabled.
+  pub connection_status: Option<ConnectionStatus>,
+}
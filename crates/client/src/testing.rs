@@ -0,0 +1,199 @@
+//! A minimal in-process gateway for client tests.
+
+use std::{
+  net::SocketAddr,
+  sync::{Arc, Mutex},
+};
+use tokio::{
+  io::{AsyncReadExt, AsyncWriteExt},
+  net::{TcpListener, TcpStream},
+};
+
+const DELIMITER: &str = "\r\n\r\n";
+
+const VERSION_RES: &str = r#"{"CMD":"VERSION_RES","PROTOCOL":"0.03","FIRMWARE":"0.91","HARDWARE":"73354100","ENET":"45068"}"#;
+const ITEM_VALUE_RES: &str = r#"{"CMD":"ITEM_VALUE_RES","PROTOCOL":"0.03"}"#;
+const ITEM_VALUE_SIGN_IN_RES: &str = r#"{"CMD":"ITEM_VALUE_SIGN_IN_RES","PROTOCOL":"0.03"}"#;
+
+/// Dimmers numbered `0..count`, all of them reported as devices.
+pub(crate) fn dimmers(count: u32) -> FakeGateway {
+  let items = (0..count)
+    .map(|n| {
+      format!(
+        r#"{{"TYPE":"DIMMER","NUMBER":{},"NAME":"Dimmer {}"}}"#,
+        n, n
+      )
+    })
+    .collect::<Vec<_>>();
+
+  FakeGateway {
+    items,
+    channels: vec![1; count as usize],
+    unsolicited: Vec::new(),
+    replies: Vec::new(),
+    ignored: Vec::new(),
+  }
+}
+
+/// Canned gateway state. Every connection is answered with the same project.
+pub(crate) struct FakeGateway {
+  items: Vec<String>,
+  channels: Vec<u32>,
+  unsolicited: Vec<(String, String)>,
+  replies: Vec<(String, String)>,
+  ignored: Vec<String>,
+}
+
+impl FakeGateway {
+  /// Push `frame` after receiving a request for `cmd`, before the response.
+  pub(crate) fn push_before(mut self, cmd: &str, frame: &str) -> Self {
+    self.unsolicited.push((cmd.to_owned(), frame.to_owned()));
+    self
+  }
+
+  /// Add blinds, numbered after the devices that are already there.
+  pub(crate) fn with_blinds(mut self) -> Self {
+    let number = self.items.len();
+    self.items.push(format!(
+      r#"{{"TYPE":"JALOUSIE","NUMBER":{},"NAME":"Blinds {}"}}"#,
+      number, number
+    ));
+    self.channels.push(1);
+    self
+  }
+
+  pub(crate) async fn start(self) -> RunningGateway {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let project = Arc::new(self);
+
+    let recorded = requests.clone();
+    tokio::spawn(async move {
+      while let Ok((stream, _)) = listener.accept().await {
+        tokio::spawn(serve(stream, project.clone(), recorded.clone()));
+      }
+    });
+
+    RunningGateway { addr, requests }
+  }
+
+  /// Answer requests for `cmd` with `frame` instead of the usual response.
+  pub(crate) fn reply_with(mut self, cmd: &str, frame: &str) -> Self {
+    self.replies.push((cmd.to_owned(), frame.to_owned()));
+    self
+  }
+
+  /// Never answer requests for `cmd`.
+  pub(crate) fn ignore(mut self, cmd: &str) -> Self {
+    self.ignored.push(cmd.to_owned());
+    self
+  }
+
+  fn unsolicited<'a>(&'a self, request: &'a str) -> impl Iterator<Item = &'a str> {
+    self
+      .unsolicited
+      .iter()
+      .filter(move |(cmd, _)| is_cmd(request, cmd))
+      .map(|(_, frame)| &**frame)
+  }
+
+  fn respond(&self, request: &str) -> Option<String> {
+    if self.ignored.iter().any(|cmd| is_cmd(request, cmd)) {
+      return None;
+    }
+
+    if let Some((_, frame)) = self.replies.iter().find(|(cmd, _)| is_cmd(request, cmd)) {
+      return Some(frame.clone());
+    }
+
+    let res = if is_cmd(request, "VERSION_REQ") {
+      VERSION_RES.to_owned()
+    } else if is_cmd(request, "GET_CHANNEL_INFO_ALL_REQ") {
+      format!(
+        r#"{{"CMD":"GET_CHANNEL_INFO_ALL_RES","PROTOCOL":"0.03","DEVICES":{:?}}}"#,
+        self.channels
+      )
+    } else if is_cmd(request, "PROJECT_LIST_GET") {
+      format!(
+        r#"{{"CMD":"PROJECT_LIST_RES","PROTOCOL":"0.03","PROJECT_ID":"1","ITEMS":[{}],"LISTS":[]}}"#,
+        self.items.join(",")
+      )
+    } else if is_cmd(request, "ITEM_VALUE_SET") {
+      ITEM_VALUE_RES.to_owned()
+    } else if is_cmd(request, "ITEM_VALUE_SIGN_IN_REQ") {
+      ITEM_VALUE_SIGN_IN_RES.to_owned()
+    } else {
+      return None;
+    };
+
+    Some(res)
+  }
+}
+
+pub(crate) struct RunningGateway {
+  addr: SocketAddr,
+  requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl RunningGateway {
+  pub(crate) fn addr(&self) -> SocketAddr {
+    self.addr
+  }
+
+  /// Frames received so far for the given command, in order.
+  pub(crate) fn requests(&self, cmd: &str) -> Vec<String> {
+    self
+      .requests
+      .lock()
+      .unwrap()
+      .iter()
+      .filter(|r| is_cmd(r, cmd))
+      .cloned()
+      .collect()
+  }
+}
+
+fn is_cmd(request: &str, cmd: &str) -> bool {
+  request.contains(&format!(r#""CMD":"{}""#, cmd))
+}
+
+async fn serve(
+  mut stream: TcpStream,
+  gateway: Arc<FakeGateway>,
+  requests: Arc<Mutex<Vec<String>>>,
+) {
+  let mut buf = Vec::new();
+  let mut chunk = [0; 4096];
+  loop {
+    let read = match stream.read(&mut chunk).await {
+      Ok(0) | Err(_) => return,
+      Ok(read) => read,
+    };
+    buf.extend_from_slice(&chunk[..read]);
+
+    while let Some(end) = find(&buf, DELIMITER.as_bytes()) {
+      let frame = String::from_utf8(buf[..end].to_vec()).unwrap();
+      buf.drain(..end + DELIMITER.len());
+
+      let mut out = String::new();
+      for pushed in gateway.unsolicited(&frame) {
+        out.push_str(pushed);
+        out.push_str(DELIMITER);
+      }
+      if let Some(res) = gateway.respond(&frame) {
+        out.push_str(&res);
+        out.push_str(DELIMITER);
+      }
+
+      requests.lock().unwrap().push(frame);
+      if stream.write_all(out.as_bytes()).await.is_err() {
+        return;
+      }
+    }
+  }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+  haystack.windows(needle.len()).position(|w| w == needle)
+}
@@ -0,0 +1,169 @@
+use crate::dev::{Device, DeviceKind, DeviceValue, EnetDevice};
+use futures::{
+  future::ready,
+  stream::{self, Stream, StreamExt},
+};
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+/// The state of a device at a point in time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceSnapshot {
+  pub number: u32,
+  pub name: Arc<str>,
+  pub kind: DeviceKind,
+  pub value: DeviceValue,
+}
+
+impl From<&Device> for DeviceSnapshot {
+  fn from(device: &Device) -> Self {
+    Self {
+      number: device.number(),
+      name: device.shared_name(),
+      kind: device.kind(),
+      value: device.value(),
+    }
+  }
+}
+
+/// A new value for a device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceUpdate {
+  pub number: u32,
+  pub value: DeviceValue,
+}
+
+/// The optimistic updates of a single
+/// [`EnetClient::set_values_transaction`](crate::EnetClient::set_values_transaction)
+/// call, published together.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceTransaction {
+  /// Id returned by the call that started the transaction.
+  pub id: u64,
+
+  /// The values of the devices in the transaction, after all of them were
+  /// updated.
+  pub updates: Vec<DeviceUpdate>,
+}
+
+/// Item of [`EnetClient::device_events`](crate::EnetClient::device_events).
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceEvent {
+  /// A new value for a device.
+  Value(DeviceUpdate),
+
+  /// The device had a known value and became [`DeviceValue::Undefined`].
+  /// Follows the [`DeviceEvent::Value`] carrying the undefined value.
+  WentOffline { number: u32 },
+
+  /// The device was [`DeviceValue::Undefined`] and got a known value again.
+  /// Follows the [`DeviceEvent::Value`] carrying the new value.
+  CameOnline { number: u32 },
+}
+
+/// Item of [`EnetClient::devices_snapshot_stream`](crate::EnetClient::devices_snapshot_stream).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InitialOrDelta {
+  /// The state of all devices at the time of subscribing. Always the first item.
+  Initial(Vec<DeviceSnapshot>),
+
+  /// A new value for a single device.
+  Delta(DeviceUpdate),
+}
+
+pub(crate) fn device_updates(devices: &[Device]) -> impl Stream<Item = DeviceUpdate> + Send {
+  stream::select_all(devices.iter().map(|device| {
+    let number = device.number();
+    device
+      .value_stream()
+      .map(move |value| DeviceUpdate { number, value })
+      .boxed()
+  }))
+}
+
+pub(crate) fn device_events(devices: &[Device]) -> impl Stream<Item = DeviceEvent> + Send {
+  stream::select_all(devices.iter().map(|device| {
+    let number = device.number();
+    let mut last = None;
+    device
+      .value_stream()
+      .flat_map(move |value| {
+        let online = value != DeviceValue::Undefined;
+        let transition = match last.replace(value) {
+          Some(prev) if (prev != DeviceValue::Undefined) != online => Some(if online {
+            DeviceEvent::CameOnline { number }
+          } else {
+            DeviceEvent::WentOffline { number }
+          }),
+          _ => None,
+        };
+
+        stream::iter(
+          std::iter::once(DeviceEvent::Value(DeviceUpdate { number, value })).chain(transition),
+        )
+      })
+      .boxed()
+  }))
+}
+
+pub(crate) fn snapshot_stream(devices: &[Device]) -> impl Stream<Item = InitialOrDelta> + Send {
+  // subscribe before taking the snapshot, so no update can fall between the two
+  let updates = device_updates(devices);
+  let snapshot = devices.iter().map(DeviceSnapshot::from).collect();
+
+  stream::once(ready(InitialOrDelta::Initial(snapshot))).chain(updates.map(InitialOrDelta::Delta))
+}
+
+pub(crate) fn transaction_stream(
+  receiver: broadcast::Receiver<DeviceTransaction>,
+) -> impl Stream<Item = DeviceTransaction> + Send {
+  stream::unfold(receiver, |mut receiver| async move {
+    loop {
+      match receiver.recv().await {
+        Ok(transaction) => return Some((transaction, receiver)),
+        // a slow subscriber misses the oldest transactions, not the newer ones
+        Err(broadcast::error::RecvError::Lagged(_)) => continue,
+        Err(broadcast::error::RecvError::Closed) => return None,
+      }
+    }
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::dev::{DeviceBrightness, DeviceDesc, DeviceState, DeviceWriter};
+
+  #[tokio::test]
+  async fn device_events_report_offline_transitions() {
+    let desc = DeviceDesc::new("Hallway".into(), 4, DeviceKind::Binary);
+    let (writer, device) = Device::new(desc, 4);
+    let mut writer = match writer {
+      DeviceWriter::Binary(w) => w,
+      _ => unreachable!(),
+    };
+    let mut events = device_events(&[device]);
+
+    let value = |value| DeviceEvent::Value(DeviceUpdate { number: 4, value });
+    writer.state_writer.write(DeviceState::On);
+    assert_eq!(
+      events.next().await,
+      Some(value(DeviceValue::On(DeviceBrightness::MAX)))
+    );
+
+    writer.state_writer.write(DeviceState::Unknown);
+    assert_eq!(events.next().await, Some(value(DeviceValue::Undefined)));
+    assert_eq!(
+      events.next().await,
+      Some(DeviceEvent::WentOffline { number: 4 })
+    );
+
+    writer.state_writer.write(DeviceState::Off);
+    assert_eq!(events.next().await, Some(value(DeviceValue::Off)));
+    assert_eq!(
+      events.next().await,
+      Some(DeviceEvent::CameOnline { number: 4 })
+    );
+  }
+}
@@ -0,0 +1,144 @@
+use crate::{dev::DeviceKind, Device, EnetDevice, ItemSetValue, ProjectItemInfo, SetValue};
+use thiserror::Error;
+
+/// Outcome of checking a single value with
+/// [`EnetClient::validate_values`](crate::EnetClient::validate_values).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationResult {
+  pub number: u32,
+  pub result: Result<(), ValidationError>,
+}
+
+impl ValidationResult {
+  #[inline]
+  pub fn is_ok(&self) -> bool {
+    self.result.is_ok()
+  }
+}
+
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ValidationError {
+  #[error("No item with number {number}.")]
+  UnknownDevice { number: u32 },
+
+  /// The item is in the project, but can't be controlled through the client.
+  /// See [`ProjectItemInfo::is_controllable`].
+  #[error("Item {number} can't be controlled.")]
+  NotWritable { number: u32 },
+
+  #[error("Item {number} is a {kind} device, which can't be set to {value}.")]
+  IncompatibleValue {
+    number: u32,
+    kind: DeviceKind,
+    value: &'static str,
+  },
+
+  #[error("Value {value} for item {number} is outside of {min}..={max}.")]
+  OutOfRange {
+    number: u32,
+    value: u8,
+    min: u8,
+    max: u8,
+  },
+}
+
+/// Check `value` against the device it targets, or the project item if there
+/// is no device for it.
+pub(crate) fn validate(
+  value: &ItemSetValue,
+  device: Option<&Device>,
+  item: Option<&ProjectItemInfo>,
+) -> Result<(), ValidationError> {
+  let number = value.number;
+  let device = match (device, item) {
+    (Some(device), _) => device,
+    (None, Some(_)) => return Err(ValidationError::NotWritable { number }),
+    (None, None) => return Err(ValidationError::UnknownDevice { number }),
+  };
+
+  let incompatible = |value| ValidationError::IncompatibleValue {
+    number,
+    kind: device.kind(),
+    value,
+  };
+
+  match (device, value.value) {
+    (_, SetValue::On(_) | SetValue::Off(_)) => Ok(()),
+    (Device::Dimmer(_), SetValue::Dimm(level)) if level <= 100 => Ok(()),
+    (Device::Dimmer(_), SetValue::Dimm(level)) => Err(ValidationError::OutOfRange {
+      number,
+      value: level,
+      min: 0,
+      max: 100,
+    }),
+    (Device::Blinds(_), SetValue::Blinds(position)) if position <= 100 => Ok(()),
+    (Device::Blinds(_), SetValue::Blinds(position)) => Err(ValidationError::OutOfRange {
+      number,
+      value: position,
+      min: 0,
+      max: 100,
+    }),
+    (_, SetValue::Dimm(_)) => Err(incompatible("a brightness")),
+    (_, SetValue::Blinds(_)) => Err(incompatible("a blinds position")),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::dev::DeviceDesc;
+
+  fn device(kind: DeviceKind) -> Device {
+    Device::new(DeviceDesc::new("Test".into(), 1, kind), 1).1
+  }
+
+  fn check(kind: DeviceKind, value: SetValue) -> Result<(), ValidationError> {
+    validate(
+      &ItemSetValue { number: 1, value },
+      Some(&device(kind)),
+      None,
+    )
+  }
+
+  #[test]
+  fn values_are_checked_against_the_device_kind() {
+    assert!(check(
+      DeviceKind::Binary,
+      SetValue::Off(enet_proto::ClickDuration::Short)
+    )
+    .is_ok());
+    assert!(check(DeviceKind::Dimmer, SetValue::Dimm(100)).is_ok());
+    assert!(check(DeviceKind::Blinds, SetValue::Blinds(30)).is_ok());
+
+    assert!(matches!(
+      check(DeviceKind::Binary, SetValue::Dimm(50)),
+      Err(ValidationError::IncompatibleValue { .. })
+    ));
+    assert!(matches!(
+      check(DeviceKind::Dimmer, SetValue::Blinds(50)),
+      Err(ValidationError::IncompatibleValue { .. })
+    ));
+    assert_eq!(
+      check(DeviceKind::Dimmer, SetValue::Dimm(101)),
+      Err(ValidationError::OutOfRange {
+        number: 1,
+        value: 101,
+        min: 0,
+        max: 100,
+      })
+    );
+  }
+
+  #[test]
+  fn missing_devices_are_reported() {
+    let value = ItemSetValue {
+      number: 1,
+      value: SetValue::Dimm(10),
+    };
+    assert_eq!(
+      validate(&value, None, None),
+      Err(ValidationError::UnknownDevice { number: 1 })
+    );
+  }
+}
@@ -0,0 +1,26 @@
+use thiserror::Error;
+
+/// Problems with the gateway project that the client recovered from while
+/// connecting.
+///
+/// These are also logged at WARN level as they are encountered; see
+/// [`EnetClient::warnings`](crate::EnetClient::warnings).
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ClientWarning {
+  /// Two devices in the project share the same item number. The first one
+  /// in project order is kept, and the other one is not exposed as a
+  /// [`Device`](crate::Device).
+  #[error("Item number {number} is used by both '{kept}' and '{dropped}'. Keeping '{kept}'.")]
+  DuplicateItemNumber {
+    number: u32,
+    kept: String,
+    dropped: String,
+  },
+
+  /// The project has more devices than
+  /// [`EnetClientBuilder::max_devices`](crate::EnetClientBuilder::max_devices)
+  /// allows. The `skipped` devices with the highest numbers are not exposed.
+  #[error("The project has {skipped} devices more than the maximum of {limit}.")]
+  TooManyDevices { limit: usize, skipped: usize },
+}
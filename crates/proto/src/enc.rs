@@ -1,7 +1,7 @@
 mod decoder;
 mod encoder;
 
-pub use decoder::{EnetDecoder, EnetDecoderError};
-pub use encoder::{EnetEncoder, EnetEncoderError};
+pub use decoder::{DecoderStats, EnetDecoder, EnetDecoderError};
+pub use encoder::{EncoderOptions, EnetEncoder, EnetEncoderError, TimestampFormat};
 
 const DELIMETER: &str = "\r\n\r\n";
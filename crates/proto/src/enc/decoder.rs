@@ -1,13 +1,48 @@
 use super::DELIMETER;
+use crate::trace::{event, Level};
 use crate::Response;
 use bytes::BytesMut;
 use lazy_static::lazy_static;
 use regex::bytes::{Regex, RegexBuilder};
+use std::borrow::Cow;
 use thiserror::Error;
-use tracing::{event, Level};
+
+/// Number of delimiters inside string values a single frame may span before
+/// the decoder gives up on it.
+const MAX_JOINED_DELIMITERS: usize = 4;
 
 lazy_static! {
   static ref DELIMETER_REGEX: Regex = RegexBuilder::new(DELIMETER).unicode(false).build().unwrap();
+  static ref CMD_REGEX: Regex = RegexBuilder::new(r#""CMD"\s*:\s*"([^"\\]*)""#)
+    .unicode(false)
+    .build()
+    .unwrap();
+}
+
+/// Counters describing the frames an [`EnetDecoder`] has processed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DecoderStats {
+  /// Number of frames split off the stream, including the ones that failed
+  /// to parse but not the empty ones, which are skipped.
+  pub frames_decoded: u64,
+
+  /// Number of bytes consumed by decoded frames, including delimiters and
+  /// empty frames.
+  pub bytes_consumed: u64,
+
+  /// Size in bytes of the largest frame seen, including the delimiter.
+  pub largest_frame: usize,
+}
+
+impl DecoderStats {
+  /// Combine the counters of two decoders.
+  pub fn merge(&self, other: &DecoderStats) -> DecoderStats {
+    DecoderStats {
+      frames_decoded: self.frames_decoded + other.frames_decoded,
+      bytes_consumed: self.bytes_consumed + other.bytes_consumed,
+      largest_frame: self.largest_frame.max(other.largest_frame),
+    }
+  }
 }
 
 #[derive(Default)]
@@ -19,39 +54,165 @@ pub struct EnetDecoder {
   // The next time `decode` is called with `abcde}`, the method will
   // only look at `de}` before returning.
   next_index: usize,
+  // Number of delimiters found inside a string value of the current frame.
+  joined: usize,
+  last_frame_len: usize,
+  last_error_cmd: Option<String>,
+  stats: DecoderStats,
 }
 
 impl EnetDecoder {
   #[inline]
   pub const fn new() -> Self {
-    Self { next_index: 0 }
+    Self {
+      next_index: 0,
+      joined: 0,
+      last_frame_len: 0,
+      last_error_cmd: None,
+      stats: DecoderStats {
+        frames_decoded: 0,
+        bytes_consumed: 0,
+        largest_frame: 0,
+      },
+    }
+  }
+
+  #[inline]
+  pub fn stats(&self) -> DecoderStats {
+    self.stats
+  }
+
+  /// Size in bytes of the last frame split off the stream, including the
+  /// delimiter, or `0` if there has been none.
+  #[inline]
+  pub fn last_frame_len(&self) -> usize {
+    self.last_frame_len
+  }
+
+  /// The `CMD` of the last frame split off the stream, if that frame failed
+  /// to parse and still names one.
+  #[inline]
+  pub fn last_error_cmd(&self) -> Option<&str> {
+    self.last_error_cmd.as_deref()
   }
 
   pub fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Response>, EnetDecoderError> {
-    match DELIMETER_REGEX.find_at(buf, self.next_index) {
-      None => {
-        // no match was found
-        let mut end_chars_matching = 0usize;
-        let mut end_iter = buf.iter().rev();
-        let mut pat_iter = DELIMETER.as_bytes().iter().rev();
-        while end_iter.next() == pat_iter.next() && end_chars_matching < DELIMETER.len() - 1 {
-          end_chars_matching += 1;
+    loop {
+      // the buffer may have been replaced by the caller since the last call
+      let start = self.next_index.min(buf.len());
+      let range = match DELIMETER_REGEX.find_at(buf, start) {
+        Some(m) => m.range(),
+        None => {
+          // no match was found, but the end of the buffer may be the start of a
+          // delimiter that is completed by the next read
+          let delimiter = DELIMETER.as_bytes();
+          let longest = (delimiter.len() - 1).min(buf.len());
+          let partial = (1..=longest)
+            .rev()
+            .find(|&len| buf.ends_with(&delimiter[..len]))
+            .unwrap_or(0);
+
+          self.next_index = buf.len() - partial;
+          return Ok(None);
         }
+      };
 
-        self.next_index = buf.len() - end_chars_matching;
-        Ok(None)
+      // a delimiter inside a string value (such as a device name) is part of
+      // the frame, so keep looking for the one after it
+      if self.joined < MAX_JOINED_DELIMITERS && ends_in_string(&buf[..range.start]) {
+        self.joined += 1;
+        self.next_index = range.end;
+        continue;
       }
 
-      Some(m) => {
-        let range = m.range();
-        self.next_index = 0;
-        let chunk_with_delimeter = buf.split_to(range.end);
-        let chunk = &chunk_with_delimeter[..chunk_with_delimeter.len() - DELIMETER.len()];
-        let item = parse(chunk)?;
-        Ok(Some(item))
+      self.next_index = 0;
+      self.joined = 0;
+      let chunk_with_delimeter = buf.split_to(range.end);
+      self.stats.bytes_consumed += range.end as u64;
+
+      // back-to-back delimiters leave nothing to parse, and reporting them as
+      // a JSON error would make the connection look broken
+      let chunk = &chunk_with_delimeter[..chunk_with_delimeter.len() - DELIMETER.len()];
+      if chunk.iter().all(u8::is_ascii_whitespace) {
+        continue;
       }
+
+      self.stats.frames_decoded += 1;
+      self.stats.largest_frame = self.stats.largest_frame.max(range.end);
+      self.last_frame_len = range.end;
+
+      return match parse(chunk) {
+        Ok(item) => {
+          self.last_error_cmd = None;
+          Ok(Some(item))
+        }
+        Err(e) => {
+          self.last_error_cmd = find_cmd(chunk);
+          Err(e)
+        }
+      };
+    }
+  }
+}
+
+/// The `CMD` field of a frame that did not parse as a whole.
+fn find_cmd(json: &[u8]) -> Option<String> {
+  let cmd = CMD_REGEX.captures(json)?.get(1)?;
+  String::from_utf8(cmd.as_bytes().to_vec()).ok()
+}
+
+/// Whether `json` stops in the middle of a string.
+fn ends_in_string(json: &[u8]) -> bool {
+  let mut in_string = false;
+  let mut escaped = false;
+  for &b in json {
+    match (in_string, escaped, b) {
+      (true, true, _) => escaped = false,
+      (true, false, b'\\') => escaped = true,
+      (true, false, b'"') => in_string = false,
+      (false, _, b'"') => in_string = true,
+      _ => (),
+    }
+  }
+
+  in_string
+}
+
+/// Escape control characters inside strings, which JSON does not allow
+/// unescaped. This is how a delimiter inside a string value gets through
+/// parsing.
+fn escape_control_characters(json: &[u8]) -> Cow<'_, [u8]> {
+  let mut in_string = false;
+  let mut escaped = false;
+  let mut out: Option<Vec<u8>> = None;
+  for (idx, &b) in json.iter().enumerate() {
+    let control = in_string && b < 0x20;
+    match (in_string, escaped, b) {
+      (true, _, _) if control => escaped = false,
+      (true, true, _) => escaped = false,
+      (true, false, b'\\') => escaped = true,
+      (true, false, b'"') => in_string = false,
+      (false, _, b'"') => in_string = true,
+      _ => (),
+    }
+
+    if control {
+      let out = out.get_or_insert_with(|| json[..idx].to_vec());
+      match b {
+        b'\n' => out.extend_from_slice(b"\\n"),
+        b'\r' => out.extend_from_slice(b"\\r"),
+        b'\t' => out.extend_from_slice(b"\\t"),
+        _ => out.extend_from_slice(format!("\\u{:04x}", b).as_bytes()),
+      }
+    } else if let Some(out) = &mut out {
+      out.push(b);
     }
   }
+
+  match out {
+    Some(out) => Cow::Owned(out),
+    None => Cow::Borrowed(json),
+  }
 }
 
 fn parse(buf: &[u8]) -> Result<Response, EnetDecoderError> {
@@ -61,7 +222,7 @@ fn parse(buf: &[u8]) -> Result<Response, EnetDecoderError> {
 
   // Due to some of the enet messages having duplicate keys, we "sanitize" the input by deserializing to serde_json::Value first
   // let value: serde_json::Value = serde_json::from_slice(buf)?;
-  let response = serde_json::from_slice(buf)?;
+  let response = serde_json::from_slice(&escape_control_characters(buf))?;
   Ok(response)
 }
 
@@ -71,3 +232,191 @@ pub enum EnetDecoderError {
   #[error("Failed to decode eNet message.")]
   JsonError(#[from] serde_json::Error),
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::EnetItem;
+
+  const VERSION_RES: &str = r#"{"CMD":"VERSION_RES","PROTOCOL":"0.03","FIRMWARE":"0.91","HARDWARE":"73354100","ENET":"45068"}"#;
+
+  fn frames(frames: &[&str]) -> BytesMut {
+    let mut buf = BytesMut::new();
+    for frame in frames {
+      buf.extend_from_slice(frame.as_bytes());
+      buf.extend_from_slice(DELIMETER.as_bytes());
+    }
+
+    buf
+  }
+
+  /// Small deterministic generator, so failures can be reproduced.
+  struct Lcg(u64);
+
+  impl Lcg {
+    fn next(&mut self, bound: usize) -> usize {
+      self.0 = self
+        .0
+        .wrapping_mul(6364136223846793005)
+        .wrapping_add(1442695040888963407);
+      ((self.0 >> 33) as usize) % bound
+    }
+  }
+
+  /// Feed `input` to a decoder in chunks of random size, returning the number
+  /// of frames that parsed.
+  fn decode_in_chunks(input: &[u8], rng: &mut Lcg) -> usize {
+    let mut decoder = EnetDecoder::new();
+    let mut buf = BytesMut::new();
+    let mut parsed = 0;
+    let mut pos = 0;
+    while pos < input.len() {
+      let len = 1 + rng.next(8);
+      let end = (pos + len).min(input.len());
+      buf.extend_from_slice(&input[pos..end]);
+      pos = end;
+
+      loop {
+        match decoder.decode(&mut buf) {
+          Ok(Some(_)) => parsed += 1,
+          Ok(None) => break,
+          Err(_) => (),
+        }
+      }
+    }
+
+    parsed
+  }
+
+  #[test]
+  fn decoder_finds_delimiters_split_across_reads() {
+    let input = frames(&[VERSION_RES, VERSION_RES, VERSION_RES]);
+    let mut rng = Lcg(1);
+    for _ in 0..200 {
+      assert_eq!(decode_in_chunks(&input, &mut rng), 3);
+    }
+
+    // a delimiter split after its first byte
+    let mut decoder = EnetDecoder::new();
+    let mut buf = BytesMut::from(&format!("{}\r", VERSION_RES)[..]);
+    assert!(decoder.decode(&mut buf).unwrap().is_none());
+    buf.extend_from_slice(b"\n\r\n");
+    assert!(decoder.decode(&mut buf).unwrap().unwrap().is_version());
+  }
+
+  #[test]
+  fn decoder_does_not_panic_on_random_input() {
+    const ALPHABET: &[u8] = b"\r\n{}\":,CMD0a";
+
+    let mut rng = Lcg(7);
+    for _ in 0..500 {
+      let len = rng.next(64);
+      let input: Vec<u8> = (0..len)
+        .map(|_| match rng.next(4) {
+          0 => rng.next(256) as u8,
+          _ => ALPHABET[rng.next(ALPHABET.len())],
+        })
+        .collect();
+
+      decode_in_chunks(&input, &mut rng);
+    }
+
+    // a buffer that shrank since the last call
+    let mut decoder = EnetDecoder::new();
+    let mut buf = BytesMut::from(&b"not a frame"[..]);
+    assert!(decoder.decode(&mut buf).unwrap().is_none());
+    buf.clear();
+    assert!(decoder.decode(&mut buf).unwrap().is_none());
+  }
+
+  #[test]
+  fn decoder_joins_delimiters_inside_strings() {
+    let project = r#"{"CMD":"PROJECT_LIST_RES","PROTOCOL":"0.03","PROJECT_ID":"1","ITEMS":[{"TYPE":"DIMMER","NUMBER":1,"NAME":"Hall\r\n\r\nway"}],"LISTS":[]}"#;
+    let input = frames(&[project, VERSION_RES]);
+
+    let mut decoder = EnetDecoder::new();
+    let mut buf = input.clone();
+    match decoder.decode(&mut buf).unwrap() {
+      Some(Response::ProjectList(res)) => {
+        assert_eq!(res.items[0].name(), "Hall\r\n\r\nway");
+      }
+      res => panic!("unexpected response: {:?}", res),
+    }
+    assert!(decoder.decode(&mut buf).unwrap().unwrap().is_version());
+    assert!(decoder.decode(&mut buf).unwrap().is_none());
+
+    // the same when the frame arrives in pieces
+    let mut rng = Lcg(3);
+    for _ in 0..50 {
+      assert_eq!(decode_in_chunks(&input, &mut rng), 2);
+    }
+  }
+
+  #[test]
+  fn decoder_gives_up_on_unterminated_strings() {
+    let broken = r#"{"CMD":"VERSION_RES","FIRMWARE":"0.91"#;
+    let mut input = frames(&[broken]);
+    for _ in 0..MAX_JOINED_DELIMITERS {
+      input.extend_from_slice(DELIMETER.as_bytes());
+    }
+    input.extend_from_slice(frames(&[VERSION_RES]).as_ref());
+
+    let mut decoder = EnetDecoder::new();
+    assert!(decoder.decode(&mut input).is_err());
+    assert!(decoder.decode(&mut input).unwrap().unwrap().is_version());
+  }
+
+  #[test]
+  fn decoder_skips_empty_frames() {
+    let mut decoder = EnetDecoder::new();
+    let mut buf = BytesMut::from(&b"\r\n\r\n\r\n\r\n"[..]);
+    assert!(decoder.decode(&mut buf).unwrap().is_none());
+    assert!(buf.is_empty());
+    assert_eq!(decoder.stats().frames_decoded, 0);
+
+    let mut buf = BytesMut::from(&b" \r\n\r\n"[..]);
+    buf.extend_from_slice(frames(&[VERSION_RES]).as_ref());
+    assert!(decoder.decode(&mut buf).unwrap().unwrap().is_version());
+    assert!(decoder.decode(&mut buf).unwrap().is_none());
+  }
+
+  #[test]
+  fn decoder_remembers_the_cmd_of_bad_frames() {
+    let mut decoder = EnetDecoder::new();
+    let mut buf = frames(&[
+      r#"{"CMD": "ITEM_UPDATE_IND","VALUES":[}"#,
+      r#"{"CMD":}"#,
+      VERSION_RES,
+    ]);
+
+    assert!(decoder.decode(&mut buf).is_err());
+    assert_eq!(decoder.last_error_cmd(), Some("ITEM_UPDATE_IND"));
+    assert!(decoder.decode(&mut buf).is_err());
+    assert_eq!(decoder.last_error_cmd(), None);
+    assert!(decoder.decode(&mut buf).unwrap().unwrap().is_version());
+    assert_eq!(decoder.last_error_cmd(), None);
+  }
+
+  #[test]
+  fn decoder_tracks_stats() {
+    let frame_len = VERSION_RES.len() + DELIMETER.len();
+    let mut decoder = EnetDecoder::new();
+    let mut buf = frames(&[VERSION_RES, VERSION_RES]);
+
+    assert!(decoder.decode(&mut buf).unwrap().unwrap().is_version());
+    assert!(decoder.decode(&mut buf).unwrap().unwrap().is_version());
+    assert!(decoder.decode(&mut buf).unwrap().is_none());
+    assert_eq!(
+      decoder.last_frame_len(),
+      VERSION_RES.len() + DELIMETER.len()
+    );
+    assert_eq!(
+      decoder.stats(),
+      DecoderStats {
+        frames_decoded: 2,
+        bytes_consumed: 2 * frame_len as u64,
+        largest_frame: frame_len,
+      }
+    );
+  }
+}
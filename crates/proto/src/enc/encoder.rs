@@ -1,23 +1,97 @@
 use bytes::{BufMut, BytesMut};
+use serde::Serialize;
+use std::time::UNIX_EPOCH;
 use thiserror::Error;
 
-use crate::RequestEnvelope;
+use crate::{ProtocolVersion, Request, RequestEnvelope};
 
 use super::DELIMETER;
 
+/// How the `TIMESTAMP` of a request is written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimestampFormat {
+  /// Seconds since the Unix epoch as a JSON string, like `"1600000000"`.
+  /// This is what the gateway apps send.
+  String,
+
+  /// Seconds since the Unix epoch as a JSON number, like `1600000000`.
+  Number,
+}
+
+impl Default for TimestampFormat {
+  fn default() -> Self {
+    TimestampFormat::String
+  }
+}
+
+/// Options for the JSON written by an [`EnetEncoder`].
+///
+/// The defaults match the requests of the gateway apps. Requests carry no
+/// floating point values, so there is nothing to configure for them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EncoderOptions {
+  pub timestamp: TimestampFormat,
+}
+
+/// A request envelope with the timestamp as a number.
+#[derive(Serialize)]
+#[serde(rename_all = "UPPERCASE")]
+struct NumericTimestampEnvelope<'a> {
+  #[serde(flatten)]
+  body: &'a Request,
+  protocol: &'a ProtocolVersion,
+  timestamp: u64,
+}
+
 #[derive(Default)]
-pub struct EnetEncoder;
+pub struct EnetEncoder {
+  options: EncoderOptions,
+}
 
 impl EnetEncoder {
   #[inline]
   pub const fn new() -> Self {
-    Self
+    Self {
+      options: EncoderOptions {
+        timestamp: TimestampFormat::String,
+      },
+    }
+  }
+
+  #[inline]
+  pub const fn with_options(options: EncoderOptions) -> Self {
+    Self { options }
   }
 
   pub fn encode(
     &mut self,
     item: &RequestEnvelope,
     buf: &mut BytesMut,
+  ) -> Result<(), EnetEncoderError> {
+    match self.options.timestamp {
+      TimestampFormat::String => self.encode_value(item, buf),
+      TimestampFormat::Number => {
+        let timestamp = item
+          .timestamp
+          .duration_since(UNIX_EPOCH)
+          .map_or(0, |d| d.as_secs());
+        let item = NumericTimestampEnvelope {
+          body: &item.body,
+          protocol: &item.protocol,
+          timestamp,
+        };
+
+        self.encode_value(&item, buf)
+      }
+    }
+  }
+
+  /// Encode any value as a frame. Unlike [`encode`](Self::encode), nothing
+  /// checks that the value is a valid request.
+  pub fn encode_value<T: Serialize + ?Sized>(
+    &mut self,
+    item: &T,
+    buf: &mut BytesMut,
   ) -> Result<(), EnetEncoderError> {
     serde_json::to_writer(buf.writer(), item)?;
     buf.put_slice(DELIMETER.as_bytes());
@@ -32,3 +106,64 @@ pub enum EnetEncoderError {
   #[error("Failed to encode eNet message.")]
   JsonError(#[from] serde_json::Error),
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    BlockListReq, GetChannelInfoAllReq, ItemValueSetReq, ItemValueSignInReq, ItemValueSignOutReq,
+    ProjectListReq, Request, VersionReq,
+  };
+  use std::time::{Duration, SystemTime};
+
+  #[test]
+  fn encode_version_request_frame() {
+    let timestamp = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+    let envelope = RequestEnvelope::new_at(VersionReq::new(), timestamp);
+
+    let mut buf = BytesMut::new();
+    EnetEncoder::new().encode(&envelope, &mut buf).unwrap();
+
+    assert_eq!(
+      &buf[..],
+      &b"{\"CMD\":\"VERSION_REQ\",\"PROTOCOL\":\"0.03\",\"TIMESTAMP\":\"1600000000\"}\r\n\r\n"[..]
+    );
+  }
+
+  #[test]
+  fn encode_numeric_timestamp() {
+    let timestamp = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+    let envelope = RequestEnvelope::new_at(VersionReq::new(), timestamp);
+    let options = EncoderOptions {
+      timestamp: TimestampFormat::Number,
+    };
+
+    let mut buf = BytesMut::new();
+    EnetEncoder::with_options(options)
+      .encode(&envelope, &mut buf)
+      .unwrap();
+
+    assert_eq!(
+      &buf[..],
+      &b"{\"CMD\":\"VERSION_REQ\",\"PROTOCOL\":\"0.03\",\"TIMESTAMP\":1600000000}\r\n\r\n"[..]
+    );
+  }
+
+  #[test]
+  fn request_kind_names_match_the_cmd() {
+    let requests: Vec<Request> = vec![
+      VersionReq::new().into(),
+      GetChannelInfoAllReq::new().into(),
+      ItemValueSignInReq::new(vec![1]).into(),
+      ItemValueSignOutReq::new(vec![1]).into(),
+      BlockListReq::new(0).into(),
+      ProjectListReq::new().into(),
+      ItemValueSetReq::new(Vec::new()).into(),
+    ];
+
+    for request in requests {
+      let json = serde_json::to_value(&request).unwrap();
+      assert_eq!(json["CMD"], request.kind().as_str());
+    }
+  }
+}
@@ -1,10 +1,23 @@
 //! Protocol types for eNet.
+//!
+//! The message types and their `serde` impls only need `alloc`, so with
+//! `default-features = false` the crate can be used from `no_std` code that
+//! does its own IO. The encoder, the decoder, `RequestEnvelope` and the record
+//! of recent unknown responses need the `std` feature.
 
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
 mod enc;
 mod req;
 mod res;
+#[doc(hidden)]
+pub mod trace;
 mod ver;
 
+#[cfg(feature = "std")]
 pub use enc::*;
 pub use req::*;
 pub use res::*;
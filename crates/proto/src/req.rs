@@ -1,11 +1,16 @@
+#[cfg(feature = "std")]
+mod envelope;
 mod value;
 
 use crate::ProtocolVersion;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
 use derive_more::{Constructor, From, IsVariant, TryInto};
 use enum_kinds::EnumKind;
 use serde::Serialize;
-use std::{convert::TryFrom, time::SystemTime};
 
+#[cfg(feature = "std")]
+pub use envelope::RequestEnvelope;
 pub use value::*;
 
 mod sealed {
@@ -105,6 +110,23 @@ impl Request {
   }
 }
 
+impl RequestKind {
+  /// The `CMD` of the request, as sent to the gateway. Unlike the `Debug`
+  /// output, this is stable, so it can be used as a label in logs and
+  /// metrics.
+  pub const fn as_str(&self) -> &'static str {
+    match self {
+      RequestKind::Version => "VERSION_REQ",
+      RequestKind::GetChannelInfoAll => "GET_CHANNEL_INFO_ALL_REQ",
+      RequestKind::ItemValueSignIn => "ITEM_VALUE_SIGN_IN_REQ",
+      RequestKind::ItemValueSignOut => "ITEM_VALUE_SIGN_OUT_REQ",
+      RequestKind::BlockList => "BLOCK_LIST_REQ",
+      RequestKind::ProjectList => "PROJECT_LIST_GET",
+      RequestKind::ItemValueSet => "ITEM_VALUE_SET",
+    }
+  }
+}
+
 impl sealed::Sealed for Request {}
 impl RequestType for Request {
   fn protocol_version(&self) -> ProtocolVersion {
@@ -119,42 +141,3 @@ impl RequestType for Request {
     }
   }
 }
-
-#[derive(Debug, Serialize)]
-#[serde(rename_all = "UPPERCASE")]
-pub struct RequestEnvelope {
-  #[serde(flatten)]
-  pub body: Request,
-  pub protocol: ProtocolVersion,
-  #[serde(serialize_with = "serialize_enet_timestamp")]
-  pub timestamp: SystemTime,
-}
-
-impl RequestEnvelope {
-  pub fn new(request: impl RequestType) -> Self {
-    let protocol = request.protocol_version();
-    Self::_new(request.into(), protocol)
-  }
-
-  #[inline(never)]
-  fn _new(request: Request, protocol: ProtocolVersion) -> Self {
-    Self {
-      body: request,
-      protocol,
-      timestamp: SystemTime::now(),
-    }
-  }
-}
-
-fn serialize_enet_timestamp<S>(value: &SystemTime, serializer: S) -> Result<S::Ok, S::Error>
-where
-  S: serde::Serializer,
-{
-  let s = value
-    .duration_since(std::time::UNIX_EPOCH)
-    .unwrap()
-    .as_secs()
-    .to_string();
-
-  s.serialize(serializer)
-}
@@ -0,0 +1,48 @@
+use super::{Request, RequestType};
+use crate::ProtocolVersion;
+use serde::Serialize;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub struct RequestEnvelope {
+  #[serde(flatten)]
+  pub body: Request,
+  pub protocol: ProtocolVersion,
+  #[serde(serialize_with = "serialize_enet_timestamp")]
+  pub timestamp: SystemTime,
+}
+
+impl RequestEnvelope {
+  pub fn new(request: impl RequestType) -> Self {
+    Self::new_at(request, SystemTime::now())
+  }
+
+  /// Create an envelope with a fixed timestamp instead of the current time.
+  pub fn new_at(request: impl RequestType, timestamp: SystemTime) -> Self {
+    let protocol = request.protocol_version();
+    Self::_new(request.into(), protocol, timestamp)
+  }
+
+  #[inline(never)]
+  fn _new(request: Request, protocol: ProtocolVersion, timestamp: SystemTime) -> Self {
+    Self {
+      body: request,
+      protocol,
+      timestamp,
+    }
+  }
+}
+
+fn serialize_enet_timestamp<S>(value: &SystemTime, serializer: S) -> Result<S::Ok, S::Error>
+where
+  S: serde::Serializer,
+{
+  let s = value
+    .duration_since(UNIX_EPOCH)
+    .unwrap()
+    .as_secs()
+    .to_string();
+
+  s.serialize(serializer)
+}
@@ -1,3 +1,4 @@
+use core::{convert::TryFrom, time::Duration};
 use serde::{ser::SerializeStruct, Serialize};
 
 #[derive(Debug, Clone, Serialize)]
@@ -12,15 +13,94 @@ pub struct ItemSetValue {
 // TODO: This should use the same kind of structure as DeviceValue
 #[derive(Debug, Clone, Copy)]
 pub enum SetValue {
+  /// Press the on button, sent as `STATE: ON`.
+  ///
+  /// Most dimmers go back to the brightness they had before they were
+  /// turned off, unlike [`SetValue::Dimm`] with `100`.
   On(ClickDuration),
 
+  /// Press the off button, sent as `STATE: OFF`.
   Off(ClickDuration),
 
+  /// Dim to a brightness in percent, sent as `VALUE_DIMM`.
   Dimm(u8),
 
+  /// Move blinds to a position, sent as `VALUE_BLINDS`.
+  ///
+  /// The gateway only has this one value for blinds. There is no separate
+  /// value for the slat angle of venetian blinds.
   Blinds(u8),
 }
 
+impl SetValue {
+  /// Turn the device on, restoring the last brightness of a dimmer.
+  #[inline]
+  pub const fn turn_on() -> Self {
+    SetValue::On(ClickDuration::Short)
+  }
+
+  /// Turn the device off. A dimmer remembers its brightness for
+  /// [`turn_on`](Self::turn_on).
+  #[inline]
+  pub const fn turn_off() -> Self {
+    SetValue::Off(ClickDuration::Short)
+  }
+
+  /// Dim to full brightness, regardless of the last brightness.
+  #[inline]
+  pub const fn full_brightness() -> Self {
+    SetValue::Dimm(100)
+  }
+
+  /// Dim all the way down to 0%, the counterpart of
+  /// [`full_brightness`](Self::full_brightness).
+  #[inline]
+  pub const fn zero_brightness() -> Self {
+    SetValue::Dimm(0)
+  }
+
+  /// Turn the device on by holding the button for the given duration.
+  #[inline]
+  pub const fn on_hold(duration: Duration) -> Self {
+    SetValue::On(ClickDuration::Hold(duration))
+  }
+
+  /// Turn the device off by holding the button for the given duration.
+  #[inline]
+  pub const fn off_hold(duration: Duration) -> Self {
+    SetValue::Off(ClickDuration::Hold(duration))
+  }
+
+  /// Dim the device to `fraction` of its full brightness, where `0.0` is off
+  /// and `1.0` is full brightness.
+  ///
+  /// Values outside of `0.0..=1.0` are clamped, and the result is rounded
+  /// half-up to a whole percent, so `0.995` and up give `Dimm(100)`. Returns
+  /// `None` if `fraction` is NaN or infinite.
+  pub fn dimm_fraction(fraction: f32) -> Option<Self> {
+    if !fraction.is_finite() {
+      return None;
+    }
+
+    Self::dimm_percent(fraction.clamp(0.0, 1.0) * 100.0)
+  }
+
+  /// Dim the device to `percent` of its full brightness.
+  ///
+  /// Values outside of `0.0..=100.0` are clamped, and the result is rounded
+  /// half-up to a whole percent. Returns `None` if `percent` is NaN or
+  /// infinite.
+  pub fn dimm_percent(percent: f32) -> Option<Self> {
+    if !percent.is_finite() {
+      return None;
+    }
+
+    // the value is not negative after clamping, so rounding half away from
+    // zero is the same as rounding half-up
+    Some(SetValue::Dimm(percent.clamp(0.0, 100.0).round() as u8))
+  }
+}
+
 impl Serialize for SetValue {
   fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
   where
@@ -45,6 +125,21 @@ impl Serialize for SetValue {
       s.end()
     }
 
+    fn serialize_state_hold<S>(
+      state: &str,
+      duration: &Duration,
+      serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+      S: serde::Serializer,
+    {
+      let millis = u64::try_from(duration.as_millis()).unwrap_or(u64::MAX);
+      let mut s = serializer.serialize_struct("SetValue", 2)?;
+      s.serialize_field("STATE", state)?;
+      s.serialize_field("LONG_CLICK", &millis)?;
+      s.end()
+    }
+
     fn serialize_value<S>(state: &str, value: &u8, serializer: S) -> Result<S::Ok, S::Error>
     where
       S: serde::Serializer,
@@ -58,8 +153,10 @@ impl Serialize for SetValue {
     match self {
       SetValue::On(ClickDuration::Short) => serialize_state("ON", serializer),
       SetValue::On(ClickDuration::Long) => serialize_state_long("ON", serializer),
+      SetValue::On(ClickDuration::Hold(d)) => serialize_state_hold("ON", d, serializer),
       SetValue::Off(ClickDuration::Short) => serialize_state("OFF", serializer),
       SetValue::Off(ClickDuration::Long) => serialize_state_long("OFF", serializer),
+      SetValue::Off(ClickDuration::Hold(d)) => serialize_state_hold("OFF", d, serializer),
       SetValue::Dimm(v) => serialize_value("VALUE_DIMM", v, serializer),
       SetValue::Blinds(v) => serialize_value("VALUE_BLINDS", v, serializer),
     }
@@ -70,6 +167,12 @@ impl Serialize for SetValue {
 pub enum ClickDuration {
   Short,
   Long,
+
+  /// Hold for a specific amount of time, for instance to run a dimming ramp.
+  ///
+  /// Sent as the hold time in milliseconds in the `LONG_CLICK` field, in place
+  /// of the fixed `ON` used by [`ClickDuration::Long`].
+  Hold(Duration),
 }
 
 #[cfg(test)]
@@ -146,6 +249,40 @@ mod tests {
     )
   }
 
+  #[test]
+  fn item_set_value_on_hold() {
+    assert_ser_tokens(
+      &item(SetValue::on_hold(Duration::from_millis(1500))),
+      &[
+        Token::Map { len: None },
+        Token::Str("STATE"),
+        Token::Str("ON"),
+        Token::Str("LONG_CLICK"),
+        Token::U64(1500),
+        Token::Str("NUMBER"),
+        Token::U32(1),
+        Token::MapEnd,
+      ],
+    )
+  }
+
+  #[test]
+  fn item_set_value_off_hold() {
+    assert_ser_tokens(
+      &item(SetValue::off_hold(Duration::from_secs(2))),
+      &[
+        Token::Map { len: None },
+        Token::Str("STATE"),
+        Token::Str("OFF"),
+        Token::Str("LONG_CLICK"),
+        Token::U64(2000),
+        Token::Str("NUMBER"),
+        Token::U32(1),
+        Token::MapEnd,
+      ],
+    )
+  }
+
   #[test]
   fn item_set_value_dim() {
     assert_ser_tokens(
@@ -163,6 +300,66 @@ mod tests {
     )
   }
 
+  #[test]
+  fn item_set_value_named_constructors() {
+    let state = |value, state| {
+      assert_ser_tokens(
+        &item(value),
+        &[
+          Token::Map { len: None },
+          Token::Str("STATE"),
+          Token::Str(state),
+          Token::Str("NUMBER"),
+          Token::U32(1),
+          Token::MapEnd,
+        ],
+      )
+    };
+    let dimm = |value, level| {
+      assert_ser_tokens(
+        &item(value),
+        &[
+          Token::Map { len: None },
+          Token::Str("STATE"),
+          Token::Str("VALUE_DIMM"),
+          Token::Str("VALUE"),
+          Token::U8(level),
+          Token::Str("NUMBER"),
+          Token::U32(1),
+          Token::MapEnd,
+        ],
+      )
+    };
+
+    state(SetValue::turn_on(), "ON");
+    state(SetValue::turn_off(), "OFF");
+    dimm(SetValue::full_brightness(), 100);
+    dimm(SetValue::zero_brightness(), 0);
+  }
+
+  #[test]
+  fn dimm_from_floats() {
+    let dimm = |v: Option<SetValue>| match v {
+      Some(SetValue::Dimm(v)) => Some(v),
+      _ => None,
+    };
+
+    assert_eq!(dimm(SetValue::dimm_fraction(0.0)), Some(0));
+    assert_eq!(dimm(SetValue::dimm_fraction(0.5)), Some(50));
+    assert_eq!(dimm(SetValue::dimm_fraction(0.995)), Some(100));
+    assert_eq!(dimm(SetValue::dimm_fraction(1.0)), Some(100));
+    assert_eq!(dimm(SetValue::dimm_fraction(2.0)), Some(100));
+    assert_eq!(dimm(SetValue::dimm_fraction(-0.5)), Some(0));
+    assert_eq!(dimm(SetValue::dimm_fraction(f32::MAX)), Some(100));
+    assert_eq!(dimm(SetValue::dimm_percent(42.5)), Some(43));
+    assert_eq!(dimm(SetValue::dimm_percent(42.4)), Some(42));
+    assert_eq!(dimm(SetValue::dimm_percent(100.0)), Some(100));
+    assert_eq!(dimm(SetValue::dimm_percent(250.0)), Some(100));
+    assert!(SetValue::dimm_fraction(f32::NAN).is_none());
+    assert!(SetValue::dimm_percent(f32::INFINITY).is_none());
+    assert!(SetValue::dimm_percent(f32::NEG_INFINITY).is_none());
+  }
+
   #[test]
   fn item_set_value_blinds() {
     assert_ser_tokens(
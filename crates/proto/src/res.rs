@@ -1,17 +1,27 @@
 mod de;
 mod proj;
+#[cfg(feature = "std")]
+mod recent;
 mod update;
 
+use crate::trace::{event, Level};
 use crate::ProtocolVersion;
+use alloc::{
+  string::{String, ToString},
+  vec::Vec,
+};
+use core::convert::TryFrom;
 use derive_more::{From, IsVariant};
 use enum_kinds::EnumKind;
 use serde::{Deserialize, Deserializer};
 use serde_json::Value;
-use std::convert::TryFrom;
-use tracing::{event, Level};
 
 pub use proj::*;
-pub use update::ItemUpdateValue;
+#[cfg(feature = "std")]
+pub use recent::{
+  recent_unknown_responses, set_unknown_response_capacity, DEFAULT_UNKNOWN_RESPONSE_CAPACITY,
+};
+pub use update::{InvalidUpdateValue, ItemUpdateValue};
 
 const FIELD_NAME_PROTOCOL: &str = "PROTOCOL";
 const FIELD_NAME_KIND: &str = "CMD";
@@ -80,14 +90,24 @@ pub struct ProjectListRes {
 }
 impl_response_type!(ProjectListRes => ProtocolVersion::ZeroZeroThree);
 
+/// Entries that can't be read are collected in `invalid` instead of failing
+/// the whole frame.
 #[derive(Debug, Deserialize)]
-#[serde(rename_all = "UPPERCASE")]
+#[serde(from = "update::RawItemUpdateInd")]
 pub struct ItemUpdateInd {
   pub values: Vec<ItemUpdateValue>,
+  pub invalid: Vec<InvalidUpdateValue>,
+}
+
+impl From<update::RawItemUpdateInd> for ItemUpdateInd {
+  fn from(raw: update::RawItemUpdateInd) -> Self {
+    let (values, invalid) = raw.into_values();
+    Self { values, invalid }
+  }
 }
 impl_response_type!(ItemUpdateInd => ProtocolVersion::ZeroZeroThree);
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct UnknownRes {
   pub kind: String,
   pub protocol: String,
@@ -142,6 +162,26 @@ impl Response {
   }
 }
 
+impl ResponseKind {
+  /// The `CMD` of the response, as sent by the gateway. Unlike the `Debug`
+  /// output, this is stable, so it can be used as a label in logs and
+  /// metrics.
+  ///
+  /// Responses the client doesn't know are all reported as `"UNKNOWN"`; the
+  /// actual `CMD` is in [`UnknownRes::kind`].
+  pub const fn as_str(&self) -> &'static str {
+    match self {
+      ResponseKind::Version => "VERSION_RES",
+      ResponseKind::GetChannelInfoAll => "GET_CHANNEL_INFO_ALL_RES",
+      ResponseKind::ItemValueSignIn => "ITEM_VALUE_SIGN_IN_RES",
+      ResponseKind::ItemValue => "ITEM_VALUE_RES",
+      ResponseKind::ProjectList => "PROJECT_LIST_RES",
+      ResponseKind::ItemUpdate => "ITEM_UPDATE_IND",
+      ResponseKind::Unknown => "UNKNOWN",
+    }
+  }
+}
+
 macro_rules! match_response {
   ($kind:ident, $protocol:ident, $deserializer:ident => {
     $(($k:ident, $v:ident) => $t:ty),*$(,)?
@@ -188,9 +228,13 @@ where
   let json = serde_json::Value::deserialize(deserializer)?;
 
   event!(target: "enet-proto::res", Level::WARN, %kind, %protocol, %json, "received unknown response type");
-  Ok(Response::Unknown(UnknownRes {
+  let res = UnknownRes {
     kind: kind.to_string(),
     protocol: protocol.to_string(),
     values: json,
-  }))
+  };
+
+  #[cfg(feature = "std")]
+  recent::record(&res);
+  Ok(Response::Unknown(res))
 }
@@ -1,16 +1,17 @@
+use alloc::{boxed::Box, string::String, vec::Vec};
+use core::{convert::Infallible, fmt, marker::PhantomData, str};
 use serde::{
   de::{self, DeserializeSeed, EnumAccess, Expected, MapAccess, SeqAccess, Unexpected, Visitor},
   forward_to_deserialize_any, Deserialize, Deserializer,
 };
 use smol_str::SmolStr;
-use std::{convert::Infallible, fmt, marker::PhantomData, str};
 
 use crate::Response;
 
 use super::{FIELD_NAME_KIND, FIELD_NAME_PROTOCOL};
 
 mod size_hint {
-  use std::cmp;
+  use core::cmp;
 
   pub(crate) fn from_bounds<I>(iter: &I) -> Option<usize>
   where
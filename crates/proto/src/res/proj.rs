@@ -1,10 +1,11 @@
+use alloc::{string::String, vec::Vec};
+use core::fmt;
 use derive_more::IsVariant;
 use enum_kinds::EnumKind;
 use serde::{
   de::{Unexpected, Visitor},
   Deserialize, Deserializer,
 };
-use std::fmt;
 
 pub trait EnetItem {
   fn number(&self) -> u32;
@@ -95,6 +96,7 @@ impl EnetItem for ProjectItem {
 pub struct ProjectScene {
   pub number: u32,
   pub name: String,
+  #[serde(deserialize_with = "deserialize_bool_lenient")]
   pub dimmable: bool,
 }
 impl_item!(ProjectScene => false);
@@ -104,7 +106,7 @@ impl_item!(ProjectScene => false);
 pub struct ProjectBinaer {
   pub number: u32,
   pub name: String,
-  #[serde(default = "get_true", deserialize_with = "deserialize_programmable")]
+  #[serde(default = "get_true", deserialize_with = "deserialize_bool_lenient")]
   pub programmable: bool,
 }
 impl_item!(ProjectBinaer : v => v.programmable);
@@ -140,12 +142,13 @@ pub struct ProjectList {
   pub name: String,
   #[serde(default)]
   pub items_order: Vec<u32>,
+  #[serde(deserialize_with = "deserialize_bool_lenient")]
   pub visible: bool,
 }
 
-struct ProgrammableVisitor;
+struct LenientBoolVisitor;
 
-impl<'de> Visitor<'de> for ProgrammableVisitor {
+impl<'de> Visitor<'de> for LenientBoolVisitor {
   type Value = bool;
 
   fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
@@ -173,14 +176,49 @@ impl<'de> Visitor<'de> for ProgrammableVisitor {
   }
 }
 
-fn deserialize_programmable<'de, D>(serializer: D) -> Result<bool, D::Error>
+/// Some firmwares send booleans as `"true"`/`"FALSE"` strings, so every bool
+/// in the project accepts both.
+fn deserialize_bool_lenient<'de, D>(serializer: D) -> Result<bool, D::Error>
 where
   D: Deserializer<'de>,
 {
-  serializer.deserialize_any(ProgrammableVisitor)
+  serializer.deserialize_any(LenientBoolVisitor)
 }
 
 #[inline]
 fn get_true() -> bool {
   true
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn project_bools_may_be_strings() {
+    let scene: ProjectScene =
+      serde_json::from_str(r#"{"NUMBER":1,"NAME":"Evening","DIMMABLE":"TRUE"}"#).unwrap();
+    assert!(scene.dimmable);
+
+    let scene: ProjectScene =
+      serde_json::from_str(r#"{"NUMBER":1,"NAME":"Evening","DIMMABLE":false}"#).unwrap();
+    assert!(!scene.dimmable);
+
+    let list: ProjectList =
+      serde_json::from_str(r#"{"NUMBER":2,"NAME":"Kitchen","VISIBLE":"false"}"#).unwrap();
+    assert!(!list.visible);
+
+    let list: ProjectList =
+      serde_json::from_str(r#"{"NUMBER":2,"NAME":"Kitchen","VISIBLE":"true"}"#).unwrap();
+    assert!(list.visible);
+
+    let binaer: ProjectBinaer =
+      serde_json::from_str(r#"{"NUMBER":3,"NAME":"Porch","PROGRAMMABLE":"FALSE"}"#).unwrap();
+    assert!(!binaer.programmable);
+
+    assert!(serde_json::from_str::<ProjectList>(
+      r#"{"NUMBER":2,"NAME":"Kitchen","VISIBLE":"maybe"}"#
+    )
+    .is_err());
+  }
+}
@@ -0,0 +1,102 @@
+use super::UnknownRes;
+use lazy_static::lazy_static;
+use std::{collections::VecDeque, sync::Mutex};
+
+/// Number of unknown responses kept by default.
+pub const DEFAULT_UNKNOWN_RESPONSE_CAPACITY: usize = 16;
+
+lazy_static! {
+  static ref RECENT: Mutex<RecentResponses> =
+    Mutex::new(RecentResponses::new(DEFAULT_UNKNOWN_RESPONSE_CAPACITY));
+}
+
+/// A ring buffer of the last `capacity` unknown responses.
+struct RecentResponses {
+  capacity: usize,
+  responses: VecDeque<UnknownRes>,
+}
+
+impl RecentResponses {
+  fn new(capacity: usize) -> Self {
+    Self {
+      capacity,
+      responses: VecDeque::new(),
+    }
+  }
+
+  fn record(&mut self, res: &UnknownRes) {
+    if self.capacity == 0 {
+      return;
+    }
+
+    while self.responses.len() >= self.capacity {
+      self.responses.pop_front();
+    }
+
+    self.responses.push_back(res.clone());
+  }
+
+  fn set_capacity(&mut self, capacity: usize) {
+    self.capacity = capacity;
+    while self.responses.len() > capacity {
+      self.responses.pop_front();
+    }
+  }
+
+  fn to_vec(&self) -> Vec<UnknownRes> {
+    self.responses.iter().cloned().collect()
+  }
+}
+
+pub(super) fn record(res: &UnknownRes) {
+  RECENT.lock().unwrap().record(res);
+}
+
+/// The most recent unknown responses decoded in this process, oldest first.
+///
+/// Only the last [`set_unknown_response_capacity`] responses are kept.
+pub fn recent_unknown_responses() -> Vec<UnknownRes> {
+  RECENT.lock().unwrap().to_vec()
+}
+
+/// Set how many unknown responses are kept for
+/// [`recent_unknown_responses`]. Setting it to 0 disables recording.
+///
+/// Defaults to [`DEFAULT_UNKNOWN_RESPONSE_CAPACITY`].
+pub fn set_unknown_response_capacity(capacity: usize) {
+  RECENT.lock().unwrap().set_capacity(capacity);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::Value;
+
+  fn unknown(kind: &str) -> UnknownRes {
+    UnknownRes {
+      kind: kind.into(),
+      protocol: "0.03".into(),
+      values: Value::Null,
+    }
+  }
+
+  fn kinds(recent: &RecentResponses) -> Vec<String> {
+    recent.to_vec().into_iter().map(|r| r.kind).collect()
+  }
+
+  #[test]
+  fn recent_unknown_responses_are_bounded() {
+    let mut recent = RecentResponses::new(2);
+    recent.record(&unknown("A"));
+    recent.record(&unknown("B"));
+    recent.record(&unknown("C"));
+    assert_eq!(kinds(&recent), ["B", "C"]);
+
+    recent.set_capacity(1);
+    assert_eq!(kinds(&recent), ["C"]);
+
+    recent.set_capacity(0);
+    recent.record(&unknown("D"));
+    assert!(kinds(&recent).is_empty());
+  }
+}
@@ -1,11 +1,97 @@
-use serde::Deserialize;
+use alloc::{
+  string::{String, ToString},
+  vec::Vec,
+};
+use serde::{de, Deserialize, Deserializer};
+use serde_json::Value;
 
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "UPPERCASE")]
 pub struct ItemUpdateValue {
-  #[serde(deserialize_with = "serde_aux::field_attributes::deserialize_number_from_string")]
+  #[serde(deserialize_with = "deserialize_item_number")]
   pub number: u32,
   pub value: String,
   pub state: String,
   pub setpoint: String,
 }
+
+/// An entry of an `ITEM_UPDATE_IND` that could not be read as an
+/// [`ItemUpdateValue`]. The other entries of the same frame are still used.
+#[derive(Debug, Clone)]
+pub struct InvalidUpdateValue {
+  /// The entry as sent by the gateway.
+  pub value: Value,
+  pub error: String,
+}
+
+/// The wire form of [`ItemUpdateInd`](super::ItemUpdateInd), where every entry
+/// is read on its own.
+#[derive(Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub(super) struct RawItemUpdateInd {
+  values: Vec<Value>,
+}
+
+impl RawItemUpdateInd {
+  pub(super) fn into_values(self) -> (Vec<ItemUpdateValue>, Vec<InvalidUpdateValue>) {
+    let mut values = Vec::with_capacity(self.values.len());
+    let mut invalid = Vec::new();
+    for value in self.values {
+      match ItemUpdateValue::deserialize(&value) {
+        Ok(v) => values.push(v),
+        Err(e) => invalid.push(InvalidUpdateValue {
+          value,
+          error: e.to_string(),
+        }),
+      }
+    }
+
+    (values, invalid)
+  }
+}
+
+/// Item numbers are usually sent as strings, sometimes padded with
+/// whitespace, and occasionally as plain numbers.
+fn deserialize_item_number<'de, D>(deserializer: D) -> Result<u32, D::Error>
+where
+  D: Deserializer<'de>,
+{
+  #[derive(Deserialize)]
+  #[serde(untagged)]
+  enum NumberOrString {
+    Number(u32),
+    String(String),
+  }
+
+  match NumberOrString::deserialize(deserializer)? {
+    NumberOrString::Number(n) => Ok(n),
+    NumberOrString::String(s) => s
+      .trim()
+      .parse()
+      .map_err(|_| de::Error::invalid_value(de::Unexpected::Str(&s), &"an item number")),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::ItemUpdateInd;
+  use alloc::{vec, vec::Vec};
+
+  #[test]
+  fn malformed_update_values_are_skipped() {
+    let json = r#"{"VALUES":[
+      {"NUMBER":"16","VALUE":"100","STATE":"ON","SETPOINT":"255"},
+      {"NUMBER":" 17 ","VALUE":"0","STATE":"OFF","SETPOINT":"255"},
+      {"NUMBER":18,"VALUE":"50","STATE":"ON","SETPOINT":"255"},
+      {"NUMBER":"","VALUE":"0","STATE":"OFF","SETPOINT":"255"},
+      {"NUMBER":"19","VALUE":"0"}
+    ]}"#;
+
+    let ind: ItemUpdateInd = serde_json::from_str(json).unwrap();
+    let numbers = ind.values.iter().map(|v| v.number).collect::<Vec<_>>();
+    assert_eq!(numbers, vec![16, 17, 18]);
+    assert_eq!(ind.invalid.len(), 2);
+    assert_eq!(ind.invalid[0].value["NUMBER"], "");
+    assert_eq!(ind.invalid[1].value["NUMBER"], "19");
+  }
+}
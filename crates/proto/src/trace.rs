@@ -0,0 +1,71 @@
+//! Logging through `tracing`, which compiles to nothing when the `tracing`
+//! feature is disabled. Shared with `enet-client`, not part of the public API.
+
+#[cfg(feature = "tracing")]
+pub use tracing::{event, Level};
+
+/// Without `tracing`, the level and the field values are still type checked
+/// in a block that never runs, so nothing is left unused.
+#[cfg(not(feature = "tracing"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __event {
+  (target: $target:expr, $level:expr, $($fields:tt)*) => {
+    $crate::__event!($level, $($fields)*)
+  };
+  ($level:expr, $($fields:tt)*) => {
+    if false {
+      let _ = $level;
+      $crate::__event_fields!($($fields)*);
+    }
+  };
+}
+
+#[cfg(not(feature = "tracing"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __event_fields {
+  () => {};
+  ($($key:ident).+ = ? $value:expr $(, $($rest:tt)*)?) => {
+    let _ = &$value;
+    $($crate::__event_fields!($($rest)*);)?
+  };
+  ($($key:ident).+ = % $value:expr $(, $($rest:tt)*)?) => {
+    let _ = &$value;
+    $($crate::__event_fields!($($rest)*);)?
+  };
+  ($($key:ident).+ = $value:expr $(, $($rest:tt)*)?) => {
+    let _ = &$value;
+    $($crate::__event_fields!($($rest)*);)?
+  };
+  (? $value:expr $(, $($rest:tt)*)?) => {
+    let _ = &$value;
+    $($crate::__event_fields!($($rest)*);)?
+  };
+  (% $value:expr $(, $($rest:tt)*)?) => {
+    let _ = &$value;
+    $($crate::__event_fields!($($rest)*);)?
+  };
+  ($value:expr $(, $($rest:tt)*)?) => {
+    let _ = &$value;
+    $($crate::__event_fields!($($rest)*);)?
+  };
+}
+
+#[cfg(not(feature = "tracing"))]
+pub use crate::__event as event;
+
+/// Stand-in for `tracing::Level`, so call sites import the same names with
+/// and without the `tracing` feature.
+#[cfg(not(feature = "tracing"))]
+#[derive(Clone, Copy)]
+pub struct Level;
+
+#[cfg(not(feature = "tracing"))]
+impl Level {
+  pub const TRACE: Level = Level;
+  pub const DEBUG: Level = Level;
+  pub const INFO: Level = Level;
+  pub const WARN: Level = Level;
+  pub const ERROR: Level = Level;
+}
@@ -1,6 +1,6 @@
+use core::{convert::Infallible, fmt, str::FromStr};
 use serde::{Deserialize, Serialize};
 use smol_str::SmolStr;
-use std::fmt;
 
 /// eNet protocol version.
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -10,6 +10,14 @@ pub enum ProtocolVersion {
   Unknown(SmolStr),
 }
 
+impl ProtocolVersion {
+  /// Whether this crate knows how to speak this protocol version.
+  #[inline]
+  pub fn is_supported(&self) -> bool {
+    !matches!(self, Self::Unknown(_))
+  }
+}
+
 impl fmt::Display for ProtocolVersion {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     match self {
@@ -19,6 +27,17 @@ impl fmt::Display for ProtocolVersion {
   }
 }
 
+impl FromStr for ProtocolVersion {
+  type Err = Infallible;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "0.03" => Ok(ProtocolVersion::ZeroZeroThree),
+      _ => Ok(ProtocolVersion::Unknown(s.into())),
+    }
+  }
+}
+
 impl Serialize for ProtocolVersion {
   fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
   where
@@ -44,10 +63,7 @@ impl<'de> serde::de::Visitor<'de> for ProtocolVisitor {
   where
     E: serde::de::Error,
   {
-    match v {
-      "0.03" => Ok(ProtocolVersion::ZeroZeroThree),
-      _ => Ok(ProtocolVersion::Unknown(v.into())),
-    }
+    Ok(v.parse().unwrap())
   }
 }
 
@@ -79,4 +95,10 @@ mod tests {
 
     assert_tokens(&version, &[Token::Str("other")])
   }
+
+  #[test]
+  fn protocol_version_is_supported() {
+    assert!("0.03".parse::<ProtocolVersion>().unwrap().is_supported());
+    assert!(!"0.04".parse::<ProtocolVersion>().unwrap().is_supported());
+  }
 }